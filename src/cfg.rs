@@ -0,0 +1,834 @@
+//! Resolving a cfg flag, e.g., `bootstrap`, to a fixed value in the sources.
+//!
+//! Upstream crates are built both by the bootstrap compiler and by the compiler being built, and
+//! tell the two apart with `cfg(bootstrap)`. The copied crates are only ever built by one
+//! compiler, so the items and attributes for the other one are dead.
+
+use std::{io, ops::Range};
+
+use crate::{
+    crate_root,
+    transform::{SourceFile, SourceTransform},
+};
+
+/// A cfg predicate, as far as a single flag is concerned.
+#[derive(Debug)]
+enum Predicate<'a> {
+    Flag(&'a str),
+    Not(Box<Predicate<'a>>),
+    All(Vec<Predicate<'a>>),
+    Any(Vec<Predicate<'a>>),
+    /// Any other predicate, e.g., `unix` or `target_os = "linux"`.
+    Other,
+}
+
+impl<'a> Predicate<'a> {
+    fn parse(predicate: &'a str) -> Predicate<'a> {
+        let predicate = predicate.trim();
+        let call = |name: &str| {
+            predicate
+                .strip_prefix(name)?
+                .trim_start()
+                .strip_prefix('(')?
+                .strip_suffix(')')
+        };
+        if let Some(argument) = call("not") {
+            Predicate::Not(Box::new(Predicate::parse(argument)))
+        } else if let Some(arguments) = call("all") {
+            Predicate::All(
+                crate_root::split_arguments(arguments)
+                    .into_iter()
+                    .map(Predicate::parse)
+                    .collect(),
+            )
+        } else if let Some(arguments) = call("any") {
+            Predicate::Any(
+                crate_root::split_arguments(arguments)
+                    .into_iter()
+                    .map(Predicate::parse)
+                    .collect(),
+            )
+        } else if predicate.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            Predicate::Flag(predicate)
+        } else {
+            Predicate::Other
+        }
+    }
+
+    /// Evaluates the predicate with `flag` set to `value`, if nothing else matters.
+    fn eval(&self, flag: &str, value: bool) -> Option<bool> {
+        match self {
+            Predicate::Flag(name) if *name == flag => Some(value),
+            Predicate::Flag(_) | Predicate::Other => None,
+            Predicate::Not(predicate) => predicate.eval(flag, value).map(|value| !value),
+            Predicate::All(predicates) => {
+                let values: Vec<_> = predicates.iter().map(|p| p.eval(flag, value)).collect();
+                if values.contains(&Some(false)) {
+                    Some(false)
+                } else if values.iter().all(Option::is_some) {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+            Predicate::Any(predicates) => {
+                let values: Vec<_> = predicates.iter().map(|p| p.eval(flag, value)).collect();
+                if values.contains(&Some(true)) {
+                    Some(true)
+                } else if values.iter().all(Option::is_some) {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Resolves `cfg(flag)`, `cfg_attr(flag, ...)` and `cfg!(flag)`, including under `not`, `all`
+/// and `any`, to the configured value of `flag`.
+///
+/// Items under a false `#[cfg]` are removed along with their attributes and doc comments,
+/// attributes under a true `#[cfg_attr]` are applied unconditionally, and `cfg!` becomes a
+/// literal. Predicates that depend on other flags are left alone.
+pub struct ResolveCfg {
+    flag: String,
+    value: bool,
+}
+
+impl ResolveCfg {
+    pub fn new(flag: &str, value: bool) -> ResolveCfg {
+        ResolveCfg {
+            flag: flag.to_owned(),
+            value,
+        }
+    }
+
+    fn eval(&self, predicate: &str) -> Option<bool> {
+        Predicate::parse(predicate).eval(&self.flag, self.value)
+    }
+}
+
+/// A rewrite of part of a file.
+struct Rewrite {
+    range: Range<usize>,
+    replacement: String,
+    description: String,
+}
+
+impl SourceTransform for ResolveCfg {
+    fn name(&self) -> &str {
+        "resolve-cfg"
+    }
+
+    fn transform(&self, _file: &SourceFile<'_>, content: &mut String) -> io::Result<Vec<String>> {
+        let source = content.as_str();
+        let line = |offset: usize| source[..offset].matches('\n').count() + 1;
+        let mut rewrites = vec![];
+
+        let mut pos = 0;
+        while pos < source.len() {
+            if let Some(end) = skip_literal_or_comment(source, pos) {
+                pos = end;
+                continue;
+            }
+            let rest = &source[pos..];
+            let inner = rest.starts_with("#![");
+            if inner || rest.starts_with("#[") {
+                let end = match crate_root::attribute_end(source, pos) {
+                    Some(end) => end,
+                    None => break,
+                };
+                let attribute = &source[pos..end];
+                let content = source[pos + if inner { 3 } else { 2 }..end - 1].trim();
+
+                if let Some(predicate) = call(content, "cfg").filter(|_| inner) {
+                    match self.eval(predicate) {
+                        Some(true) => {
+                            rewrites.push(Rewrite {
+                                range: whole_lines(source, pos..end),
+                                replacement: String::new(),
+                                description: format!(
+                                    "removed `{}` at line {}, keeping the module",
+                                    attribute,
+                                    line(pos)
+                                ),
+                            });
+                        }
+                        // The flag is never set downstream, so the predicate must not depend on
+                        // it for the module to stay compiled out.
+                        Some(false) => {
+                            rewrites.push(Rewrite {
+                                range: pos..end,
+                                replacement: "#![cfg(any())]".to_owned(),
+                                description: format!(
+                                    "replaced `{}` with `#![cfg(any())]` at line {}, compiling \
+                                     the module out",
+                                    attribute,
+                                    line(pos)
+                                ),
+                            });
+                        }
+                        None => {}
+                    }
+                } else if let Some(predicate) = call(content, "cfg") {
+                    match self.eval(predicate) {
+                        Some(true) => {
+                            rewrites.push(Rewrite {
+                                range: whole_lines(source, pos..end),
+                                replacement: String::new(),
+                                description: format!(
+                                    "removed `{}` at line {}, keeping the item",
+                                    attribute,
+                                    line(pos)
+                                ),
+                            });
+                        }
+                        Some(false) => {
+                            let item_end = item_end(source, end);
+                            let start = leading_attributes_start(source, pos);
+                            rewrites.push(Rewrite {
+                                range: item_range(source, start..item_end),
+                                replacement: String::new(),
+                                description: format!(
+                                    "removed the item under `{}` at line {}",
+                                    attribute,
+                                    line(pos)
+                                ),
+                            });
+                            pos = item_end;
+                            continue;
+                        }
+                        None => {}
+                    }
+                } else if let Some((predicate, attributes)) = crate_root::cfg_attr(content) {
+                    match self.eval(predicate) {
+                        Some(true) => {
+                            let indent = &source[line_start(source, pos)..pos];
+                            let separator = if indent.trim().is_empty() {
+                                format!("\n{}", indent)
+                            } else {
+                                " ".to_owned()
+                            };
+                            let replacement = attributes
+                                .iter()
+                                .map(|a| format!("#{}[{}]", if inner { "!" } else { "" }, a))
+                                .collect::<Vec<_>>()
+                                .join(&separator);
+                            rewrites.push(Rewrite {
+                                range: pos..end,
+                                description: format!(
+                                    "replaced `{}` with `{}` at line {}",
+                                    attribute,
+                                    replacement.replace(&separator, " "),
+                                    line(pos)
+                                ),
+                                replacement,
+                            });
+                        }
+                        Some(false) => {
+                            rewrites.push(Rewrite {
+                                range: whole_lines(source, pos..end),
+                                replacement: String::new(),
+                                description: format!(
+                                    "removed `{}` at line {}",
+                                    attribute,
+                                    line(pos)
+                                ),
+                            });
+                        }
+                        None => {}
+                    }
+                }
+                pos = end;
+            } else if rest.starts_with("cfg!") && !is_ident_byte(source, pos.wrapping_sub(1)) {
+                let open = pos + 4 + (rest[4..].len() - rest[4..].trim_start().len());
+                let end = if source[open..].starts_with('(') {
+                    closing(source, open)
+                } else {
+                    None
+                };
+                if let Some(end) = end {
+                    if let Some(value) = self.eval(&source[open + 1..end - 1]) {
+                        rewrites.push(Rewrite {
+                            range: pos..end,
+                            replacement: value.to_string(),
+                            description: format!(
+                                "replaced `{}` with `{}` at line {}",
+                                &source[pos..end],
+                                value,
+                                line(pos)
+                            ),
+                        });
+                    }
+                    pos = end;
+                } else {
+                    pos += 4;
+                }
+            } else {
+                pos = next_char(source, pos);
+            }
+        }
+
+        let rewrites = resolve_overlaps(rewrites)?;
+        let mut result = String::with_capacity(content.len());
+        let mut last = 0;
+        let mut changes = vec![];
+        for rewrite in rewrites {
+            result.push_str(&content[last..rewrite.range.start]);
+            result.push_str(&rewrite.replacement);
+            last = rewrite.range.end;
+            changes.push(rewrite.description);
+        }
+        result.push_str(&content[last..]);
+        *content = result;
+        Ok(changes)
+    }
+}
+
+/// Drops the rewrites within a wider one from `rewrites`, which are in the order they were found,
+/// e.g., a rewritten attribute of an item removed along with its leading attributes.
+fn resolve_overlaps(rewrites: Vec<Rewrite>) -> io::Result<Vec<Rewrite>> {
+    let mut resolved: Vec<Rewrite> = vec![];
+    'rewrites: for rewrite in rewrites {
+        while let Some(previous) = resolved.last() {
+            if previous.range.end <= rewrite.range.start {
+                break;
+            }
+            let range = &rewrite.range;
+            if range.start <= previous.range.start && previous.range.end <= range.end {
+                resolved.pop();
+            } else if previous.range.start <= range.start && range.end <= previous.range.end {
+                continue 'rewrites;
+            } else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "cannot both {} and {}",
+                        previous.description, rewrite.description
+                    ),
+                ));
+            }
+        }
+        resolved.push(rewrite);
+    }
+    Ok(resolved)
+}
+
+/// Returns the content of a `name(...)` attribute or macro call.
+fn call<'a>(content: &'a str, name: &str) -> Option<&'a str> {
+    content
+        .strip_prefix(name)?
+        .trim_start()
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+fn is_ident_byte(source: &str, pos: usize) -> bool {
+    source
+        .as_bytes()
+        .get(pos)
+        .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_')
+}
+
+fn line_start(source: &str, pos: usize) -> usize {
+    source[..pos].rfind('\n').map_or(0, |i| i + 1)
+}
+
+/// Extends `range` to whole lines if nothing but whitespace surrounds it on its first and last
+/// lines, so that removing it leaves no blank line behind.
+fn whole_lines(source: &str, range: Range<usize>) -> Range<usize> {
+    let start = line_start(source, range.start);
+    let line_end = source[range.end..]
+        .find('\n')
+        .map_or(source.len(), |i| range.end + i + 1);
+    if source[start..range.start].trim().is_empty() && source[range.end..line_end].trim().is_empty()
+    {
+        start..line_end
+    } else {
+        range
+    }
+}
+
+/// Extends the range of a removed item like `whole_lines`, also removing one of the blank lines
+/// around it or the spaces after it, so that the surrounding code stays tidy.
+fn item_range(source: &str, range: Range<usize>) -> Range<usize> {
+    let lines = whole_lines(source, range.clone());
+    if lines == range {
+        let spaces = source[range.end..].len() - source[range.end..].trim_start_matches(' ').len();
+        return range.start..range.end + spaces;
+    }
+
+    let blank_before = lines.start == 0 || source[..lines.start - 1].ends_with('\n');
+    let next_line_end = source[lines.end..].find('\n').map(|i| lines.end + i + 1);
+    match next_line_end {
+        Some(end) if blank_before && source[lines.end..end].trim().is_empty() => lines.start..end,
+        _ => lines,
+    }
+}
+
+/// Returns the start of the doc comments and single-line outer attributes on the lines right
+/// above the attribute at `pos`, which belong to the same item.
+fn leading_attributes_start(source: &str, pos: usize) -> usize {
+    let mut start = line_start(source, pos);
+    if !source[start..pos].trim().is_empty() {
+        return pos;
+    }
+    while start > 0 {
+        let previous_start = line_start(source, start - 1);
+        let previous = source[previous_start..start].trim();
+        let is_attribute = previous.starts_with("#[") && previous.ends_with(']');
+        if !(previous.starts_with("///") || is_attribute) {
+            break;
+        }
+        start = previous_start;
+    }
+    start
+}
+
+/// Returns the end of the item, statement, field, variant or match arm starting at `start`,
+/// along with its outer attributes.
+fn item_end(source: &str, start: usize) -> usize {
+    let bytes = source.as_bytes();
+    let mut pos = start;
+    loop {
+        pos += source[pos..].len() - source[pos..].trim_start().len();
+        if let Some(end) = skip_literal_or_comment(source, pos) {
+            pos = end;
+        } else if source[pos..].starts_with("#[") {
+            match crate_root::attribute_end(source, pos) {
+                Some(end) => pos = end,
+                None => return source.len(),
+            }
+        } else {
+            break;
+        }
+    }
+
+    // The visibility, e.g., `pub(crate)`, says nothing about what follows.
+    if source[pos..].starts_with("pub") && !is_ident_byte(source, pos + 3) {
+        pos += 3;
+        pos += source[pos..].len() - source[pos..].trim_start().len();
+        if source[pos..].starts_with('(') {
+            pos = closing(source, pos).unwrap_or(source.len());
+            pos += source[pos..].len() - source[pos..].trim_start().len();
+        }
+    }
+
+    let first_word: String = source[pos..]
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+    let after_word = source[pos + first_word.len()..].trim_start();
+    let is_macro_call = after_word.starts_with('!');
+    let ends_at_semicolon = matches!(first_word.as_str(), "let" | "const" | "static" | "type");
+    let ends_at_comma = !is_macro_call
+        && !matches!(
+            first_word.as_str(),
+            "fn" | "struct"
+                | "enum"
+                | "union"
+                | "impl"
+                | "trait"
+                | "mod"
+                | "use"
+                | "const"
+                | "static"
+                | "type"
+                | "extern"
+                | "unsafe"
+                | "async"
+                | "let"
+                | "macro_rules"
+        );
+
+    let mut depth = 0;
+    // Types may have commas between angle brackets, e.g., `HashMap<K, V>`.
+    let mut track_angles = ends_at_comma;
+    let mut angle_depth = 0;
+    // If the angle brackets are still open at the end, they were comparisons, e.g., `a < b` in
+    // the value of a field, so the item ends at the first comma after them instead.
+    let mut first_comma = None;
+    while pos < bytes.len() {
+        if let Some(end) = skip_literal_or_comment(source, pos) {
+            pos = end;
+            continue;
+        }
+        match bytes[pos] {
+            b'<' if track_angles => match bytes.get(pos + 1) {
+                Some(b'<') => pos += 1,
+                Some(b'=') => {}
+                _ => angle_depth += 1,
+            },
+            // The body of a match arm is an expression, where `<` compares.
+            b'=' if depth == 0 && bytes.get(pos + 1) == Some(&b'>') => {
+                track_angles = false;
+                angle_depth = 0;
+                pos += 1;
+            }
+            // Not the arrow of a function type, e.g., `fn() -> u8`, nor `>=`.
+            b'>' if angle_depth > 0
+                && bytes[pos - 1] != b'-'
+                && bytes.get(pos + 1) != Some(&b'=') =>
+            {
+                angle_depth -= 1
+            }
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' if depth == 0 => return first_comma.unwrap_or(pos),
+            b')' | b']' => depth -= 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 && !ends_at_semicolon {
+                    let next =
+                        pos + 1 + (source[pos + 1..].len() - source[pos + 1..].trim_start().len());
+                    return match bytes.get(next) {
+                        Some(b';') => next + 1,
+                        Some(b',') if ends_at_comma => next + 1,
+                        _ => pos + 1,
+                    };
+                }
+            }
+            b';' if depth == 0 => return pos + 1,
+            b',' if depth == 0 && ends_at_comma => {
+                if angle_depth == 0 {
+                    return pos + 1;
+                }
+                first_comma.get_or_insert(pos + 1);
+            }
+            _ => {}
+        }
+        pos = next_char(source, pos);
+    }
+    first_comma.unwrap_or(pos)
+}
+
+/// Returns the offset after the parenthesis matching the one at `open`.
+fn closing(source: &str, open: usize) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut depth = 0;
+    let mut pos = open;
+    while pos < bytes.len() {
+        if let Some(end) = skip_literal_or_comment(source, pos) {
+            pos = end;
+            continue;
+        }
+        match bytes[pos] {
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(pos + 1);
+                }
+            }
+            _ => {}
+        }
+        pos = next_char(source, pos);
+    }
+    None
+}
+
+/// Returns the offset of the character after the one at `pos`.
+fn next_char(source: &str, pos: usize) -> usize {
+    pos + source[pos..].chars().next().map_or(1, char::len_utf8)
+}
+
+/// Returns the offset after the comment, string or character literal at `pos`, if any.
+pub fn skip_literal_or_comment(source: &str, pos: usize) -> Option<usize> {
+    // Nothing starts within a character.
+    let rest = source.get(pos..)?;
+    if rest.starts_with("//") {
+        return Some(rest.find('\n').map_or(source.len(), |i| pos + i));
+    }
+    if rest.starts_with("/*") {
+        // Comments may hold any character, so compare bytes.
+        let bytes = source.as_bytes();
+        let mut depth = 0;
+        let mut i = pos;
+        while i < bytes.len() {
+            if bytes[i..].starts_with(b"/*") {
+                depth += 1;
+                i += 2;
+            } else if bytes[i..].starts_with(b"*/") {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Some(i);
+                }
+            } else {
+                i += 1;
+            }
+        }
+        return Some(source.len());
+    }
+    if is_ident_byte(source, pos.wrapping_sub(1)) {
+        return None;
+    }
+
+    // Raw strings, e.g., `r#"..."#` or `br"..."`.
+    let raw = rest.strip_prefix("br").or_else(|| rest.strip_prefix('r'));
+    if let Some(after_r) = raw {
+        let hashes = after_r.len() - after_r.trim_start_matches('#').len();
+        if after_r[hashes..].starts_with('"') {
+            let body = pos + (rest.len() - after_r.len()) + hashes + 1;
+            let terminator = format!("\"{}", "#".repeat(hashes));
+            return Some(
+                source[body..]
+                    .find(&terminator)
+                    .map_or(source.len(), |i| body + i + terminator.len()),
+            );
+        }
+    }
+
+    let quoted = rest.strip_prefix('b').unwrap_or(rest);
+    let quote_pos = pos + (rest.len() - quoted.len());
+    if quoted.starts_with('"') {
+        let mut escaped = false;
+        for (i, c) in source[quote_pos + 1..].char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '"' if !escaped => return Some(quote_pos + 1 + i + 1),
+                _ => escaped = false,
+            }
+        }
+        return Some(source.len());
+    }
+    if let Some(literal) = quoted.strip_prefix('\'') {
+        let mut chars = literal.chars();
+        return match chars.next() {
+            // An escaped character, e.g., `'\n'` or `'\''`.
+            Some('\\') => literal.get(2..)?.find('\'').map(|i| quote_pos + 3 + i + 1),
+            // A character, e.g., `'a'`, unlike a lifetime, e.g., `'a`.
+            Some(c) if chars.next() == Some('\'') => Some(quote_pos + 1 + c.len_utf8() + 1),
+            _ => None,
+        };
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::graph::LocalCrate;
+
+    fn resolve(value: bool, source: &str) -> String {
+        let krate = LocalCrate {
+            name: "test",
+            root_path: Path::new("."),
+            targets: &[],
+        };
+        let file = SourceFile {
+            krate: &krate,
+            path: Path::new("lib.rs"),
+            is_crate_root: true,
+        };
+        let mut content = source.to_owned();
+        ResolveCfg::new("bootstrap", value)
+            .transform(&file, &mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn removes_items() {
+        assert_eq!(
+            resolve(
+                false,
+                "/// Docs.\n#[cfg(bootstrap)]\n#[inline]\nfn foo() {}\n\nfn bar() {}\n"
+            ),
+            "fn bar() {}\n"
+        );
+        assert_eq!(
+            resolve(true, "#[cfg(bootstrap)]\nfn foo() {}\n"),
+            "fn foo() {}\n"
+        );
+        assert_eq!(
+            resolve(
+                false,
+                "#[cfg(bootstrap)]\npub(crate) struct S;\nstruct T;\n"
+            ),
+            "struct T;\n"
+        );
+    }
+
+    #[test]
+    fn leaves_other_predicates_alone() {
+        let source = "#[cfg(unix)]\nfn foo() {}\n#[cfg(all(unix, bootstrap))]\nfn bar() {}\n";
+        assert_eq!(resolve(true, source), source);
+        assert_eq!(resolve(false, source), "#[cfg(unix)]\nfn foo() {}\n");
+    }
+
+    #[test]
+    fn removes_fields() {
+        assert_eq!(
+            resolve(
+                false,
+                "pub struct S { #[cfg(bootstrap)] pub a: u8, pub b: u8, pub c: u8, }"
+            ),
+            "pub struct S { pub b: u8, pub c: u8, }"
+        );
+        assert_eq!(
+            resolve(
+                false,
+                "struct S {\n    #[cfg(bootstrap)]\n    pub(crate) a: HashMap<K, V>,\n    \
+                 b: u8,\n}\n"
+            ),
+            "struct S {\n    b: u8,\n}\n"
+        );
+        assert_eq!(
+            resolve(
+                false,
+                "struct S {\n    #[cfg(bootstrap)]\n    a: Box<dyn Fn(u8) -> u8>,\n    b: u8,\n}\n"
+            ),
+            "struct S {\n    b: u8,\n}\n"
+        );
+    }
+
+    #[test]
+    fn removes_variants() {
+        assert_eq!(
+            resolve(
+                false,
+                "enum E {\n    #[cfg(bootstrap)]\n    A { x: u8, y: u8 },\n    \
+                 #[cfg(bootstrap)]\n    \
+                 B(u8, u8),\n    C,\n}\n"
+            ),
+            "enum E {\n    C,\n}\n"
+        );
+    }
+
+    #[test]
+    fn removes_tuple_fields() {
+        assert_eq!(
+            resolve(
+                false,
+                "enum E {\n    B(#[cfg(bootstrap)] HashMap<u8, u8>, u8),\n}\n"
+            ),
+            "enum E {\n    B(u8),\n}\n"
+        );
+        assert_eq!(
+            resolve(false, "struct S(#[cfg(bootstrap)] Vec<Vec<u8>>, u8);\n"),
+            "struct S(u8);\n"
+        );
+    }
+
+    #[test]
+    fn ends_fields_with_comparisons_at_their_comma() {
+        assert_eq!(
+            resolve(
+                false,
+                "S {\n    #[cfg(bootstrap)]\n    a: x < y,\n    b: Vec::<u8>::new(),\n}\n"
+            ),
+            "S {\n    b: Vec::<u8>::new(),\n}\n"
+        );
+    }
+
+    #[test]
+    fn removes_match_arms() {
+        assert_eq!(
+            resolve(
+                false,
+                "match x {\n    #[cfg(bootstrap)]\n    A => foo(a, b),\n    #[cfg(bootstrap)]\n    \
+                 B => {\n        bar();\n    }\n    _ => {}\n}\n"
+            ),
+            "match x {\n    _ => {}\n}\n"
+        );
+        assert_eq!(
+            resolve(
+                false,
+                "match x {\n    #[cfg(bootstrap)]\n    A if a < b => a <= b,\n    _ => false,\n}\n"
+            ),
+            "match x {\n    _ => false,\n}\n"
+        );
+    }
+
+    #[test]
+    fn removes_statements() {
+        assert_eq!(
+            resolve(
+                false,
+                "fn f() {\n    #[cfg(bootstrap)]\n    let x = foo(a, b);\n    \
+                 #[cfg(bootstrap)]\n    \
+                 bar!(x);\n    baz();\n}\n"
+            ),
+            "fn f() {\n    baz();\n}\n"
+        );
+    }
+
+    #[test]
+    fn resolves_cfg_attr() {
+        let source = "#[cfg_attr(bootstrap, inline, cold)]\nfn foo() {}\n";
+        assert_eq!(resolve(true, source), "#[inline]\n#[cold]\nfn foo() {}\n");
+        assert_eq!(resolve(false, source), "fn foo() {}\n");
+        assert_eq!(
+            resolve(true, "#![cfg_attr(not(bootstrap), feature(foo))]\n"),
+            ""
+        );
+        assert_eq!(
+            resolve(false, "#![cfg_attr(not(bootstrap), feature(foo))]\n"),
+            "#![feature(foo)]\n"
+        );
+    }
+
+    #[test]
+    fn resolves_cfg_macro() {
+        assert_eq!(
+            resolve(true, "if cfg!(bootstrap) && !cfg!(not(bootstrap)) {}"),
+            "if true && !false {}"
+        );
+        assert_eq!(resolve(true, "if cfg!(unix) {}"), "if cfg!(unix) {}");
+    }
+
+    #[test]
+    fn removal_wins_over_attribute_rewrites() {
+        assert_eq!(
+            resolve(
+                true,
+                "#[cfg_attr(bootstrap, inline)]\n#[cfg(not(bootstrap))]\nfn foo() {}\n\
+                 #[cfg(bootstrap)]\nfn foo() {}\n"
+            ),
+            "fn foo() {}\n"
+        );
+    }
+
+    #[test]
+    fn resolves_inner_cfg() {
+        assert_eq!(
+            resolve(true, "#![cfg(bootstrap)]\nfn foo() {}\n"),
+            "fn foo() {}\n"
+        );
+        assert_eq!(
+            resolve(true, "#![cfg(not(bootstrap))]\nfn foo() {}\n"),
+            "#![cfg(any())]\nfn foo() {}\n"
+        );
+    }
+
+    #[test]
+    fn handles_non_ascii_characters() {
+        assert_eq!(
+            resolve(
+                false,
+                "/* café */ fn a() {}\n#[cfg(bootstrap)]\nfn b() {}\n"
+            ),
+            "/* café */ fn a() {}\n"
+        );
+        assert_eq!(
+            resolve(
+                false,
+                "enum E {\n    #[cfg(bootstrap)]\n    A(/* Ünïcode — comment */ u8),\n    \
+                 #[cfg(bootstrap)]\n    B { é: &'static str },\n    C = \"ß\".len(),\n}\n"
+            ),
+            "enum E {\n    C = \"ß\".len(),\n}\n"
+        );
+        let source = "// «cfg!(bootstrap)»\nfn f() -> char { 'é' }\n";
+        assert_eq!(resolve(false, source), source);
+    }
+
+    #[test]
+    fn skips_literals_and_comments() {
+        let source = "// #[cfg(bootstrap)]\nlet s = \"cfg!(bootstrap)\";\n\
+                      let r = r#\"#[cfg(bootstrap)]\"#;\n";
+        assert_eq!(resolve(false, source), source);
+    }
+}
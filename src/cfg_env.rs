@@ -0,0 +1,297 @@
+//! Providing the `CFG_*` environment variables, e.g., `CFG_RELEASE`, that the upstream build
+//! system sets when compiling the compiler, and that cargo alone does not.
+//!
+//! The sources read them with `env!("CFG_RELEASE")` or `option_env!("CFG_RELEASE_CHANNEL")`.
+//! They are either replaced with literals or set by a generated build script.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    cfg,
+    graph::LocalCrate,
+    transform::{SourceFile, SourceTransform},
+    version,
+};
+
+/// How the `CFG_*` environment variables are provided to a copied crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// Replace `env!` and `option_env!` with the values.
+    Literal,
+    /// Generate a `build.rs` setting the variables the sources read.
+    BuildScript,
+}
+
+/// A read of a `CFG_*` environment variable in the sources.
+#[derive(Debug)]
+pub struct Usage {
+    /// Path of the file relative to the crate root.
+    pub path: PathBuf,
+    pub line: usize,
+    pub name: String,
+    /// Whether the variable is read with `option_env!` rather than `env!`.
+    pub optional: bool,
+    range: Range<usize>,
+}
+
+/// Computes the values of the `CFG_*` variables the upstream build system sets, for the upstream
+/// checkout at `root` built on `channel`, e.g., `nightly`.
+pub fn values(root: &Path, channel: &str) -> io::Result<BTreeMap<String, String>> {
+    let release_num = fs::read_to_string(root.join("src").join("version"))?
+        .trim()
+        .to_owned();
+    let release = match channel {
+        "stable" => release_num.clone(),
+        channel => format!("{}-{}", release_num, channel),
+    };
+    let commit = version::git(root, &["rev-parse", "--short=9", "HEAD"])?;
+    let date = version::git(root, &["log", "-1", "--format=%cd", "--date=short"])?;
+
+    let mut values = BTreeMap::new();
+    values.insert(
+        "CFG_VERSION".to_owned(),
+        format!("{} ({} {})", release, commit, date),
+    );
+    values.insert("CFG_RELEASE".to_owned(), release);
+    values.insert("CFG_RELEASE_NUM".to_owned(), release_num);
+    values.insert("CFG_RELEASE_CHANNEL".to_owned(), channel.to_owned());
+    values.insert("CFG_VER_HASH".to_owned(), commit);
+    values.insert("CFG_VER_DATE".to_owned(), date);
+    Ok(values)
+}
+
+/// Returns every read of a `CFG_*` variable in the `.rs` files of `krate` among `files`.
+pub fn usages(krate: &LocalCrate<'_>, files: &BTreeSet<PathBuf>) -> io::Result<Vec<Usage>> {
+    let mut usages = vec![];
+    for path in files {
+        if path.extension().is_none_or(|extension| extension != "rs") {
+            continue;
+        }
+        let content = fs::read_to_string(krate.root_path.join(path))?;
+        usages.extend(find_usages(path, &content));
+    }
+    Ok(usages)
+}
+
+/// Generates a build script setting the variables in `names` to their `values`.
+pub fn build_script(names: &BTreeSet<&str>, values: &BTreeMap<String, String>) -> String {
+    let mut script = "// Generated by rustc-publisher: sets the environment variables that the \
+                      upstream build system sets.\n\nfn main() {\n"
+        .to_owned();
+    for name in names {
+        if let Some(value) = values.get(*name) {
+            script.push_str(&format!(
+                "    println!(\"cargo:rustc-env={}={{}}\", {:?});\n",
+                name, value
+            ));
+        }
+    }
+    script.push_str("}\n");
+    script
+}
+
+/// Replaces `env!("CFG_...")` with string literals and `option_env!("CFG_...")` with
+/// `Some(...)`. Variables without a known value are left alone.
+pub struct ReplaceCfgEnv {
+    values: BTreeMap<String, String>,
+}
+
+impl ReplaceCfgEnv {
+    pub fn new(values: BTreeMap<String, String>) -> ReplaceCfgEnv {
+        ReplaceCfgEnv { values }
+    }
+}
+
+impl SourceTransform for ReplaceCfgEnv {
+    fn name(&self) -> &str {
+        "cfg-env"
+    }
+
+    fn transform(&self, file: &SourceFile<'_>, content: &mut String) -> io::Result<Vec<String>> {
+        let mut changes = vec![];
+        let mut result = String::with_capacity(content.len());
+        let mut last = 0;
+        for usage in find_usages(file.path, content) {
+            let call = &content[usage.range.clone()];
+            let value = match self.values.get(&usage.name) {
+                Some(value) => format!("{:?}", value),
+                None => {
+                    changes.push(format!(
+                        "left `{}` at line {} alone, its value is unknown",
+                        call, usage.line
+                    ));
+                    continue;
+                }
+            };
+            let replacement = if usage.optional {
+                format!("Some({})", value)
+            } else {
+                value
+            };
+            changes.push(format!(
+                "replaced `{}` with `{}` at line {}",
+                call, replacement, usage.line
+            ));
+            result.push_str(&content[last..usage.range.start]);
+            result.push_str(&replacement);
+            last = usage.range.end;
+        }
+        result.push_str(&content[last..]);
+        *content = result;
+        Ok(changes)
+    }
+}
+
+/// Finds the `env!` and `option_env!` calls reading a `CFG_*` variable in `source`.
+fn find_usages(path: &Path, source: &str) -> Vec<Usage> {
+    let mut usages = vec![];
+    let mut pos = 0;
+    while pos < source.len() {
+        if let Some(end) = cfg::skip_literal_or_comment(source, pos) {
+            pos = end;
+            continue;
+        }
+        let rest = &source[pos..];
+        let preceded_by_ident = source[..pos]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let optional = rest.starts_with("option_env!");
+        if preceded_by_ident || !(optional || rest.starts_with("env!")) {
+            pos += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        }
+
+        let macro_len = if optional {
+            "option_env!".len()
+        } else {
+            "env!".len()
+        };
+        let arguments = rest[macro_len..].trim_start();
+        let name = arguments
+            .strip_prefix('(')
+            .map(str::trim_start)
+            .and_then(|a| a.strip_prefix('"'))
+            .and_then(|a| Some(&a[..a.find('"')?]))
+            .filter(|name| name.starts_with("CFG_"));
+        let end = name.and_then(|_| Some(pos + macro_len + rest[macro_len..].find(')')? + 1));
+        match (name, end) {
+            (Some(name), Some(end)) => {
+                usages.push(Usage {
+                    path: path.to_path_buf(),
+                    line: source[..pos].matches('\n').count() + 1,
+                    name: name.to_owned(),
+                    optional,
+                    range: pos..end,
+                });
+                pos = end;
+            }
+            _ => pos += macro_len,
+        }
+    }
+    usages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    #[test]
+    fn finds_usages() {
+        let source = "const V: &str = env!(\"CFG_RELEASE\");\n\
+                      // env!(\"CFG_VERSION\")\n\
+                      let s = \"option_env!(\\\"CFG_VER_HASH\\\")\";\n\
+                      let é = option_env!( \"CFG_RELEASE_CHANNEL\" );\n\
+                      let p = env!(\"CARGO_PKG_VERSION\");\n\
+                      let m = my_env!(\"CFG_RELEASE\");\n";
+        let usages = find_usages(Path::new("lib.rs"), source);
+        let found: Vec<_> = usages
+            .iter()
+            .map(|usage| (usage.line, usage.name.as_str(), usage.optional))
+            .collect();
+        assert_eq!(
+            found,
+            [(1, "CFG_RELEASE", false), (4, "CFG_RELEASE_CHANNEL", true)]
+        );
+        assert_eq!(
+            &source[usages[1].range.clone()],
+            "option_env!( \"CFG_RELEASE_CHANNEL\" )"
+        );
+    }
+
+    #[test]
+    fn replaces_usages_with_literals() {
+        let krate = LocalCrate {
+            name: "test",
+            root_path: Path::new("."),
+            targets: &[],
+        };
+        let file = SourceFile {
+            krate: &krate,
+            path: Path::new("lib.rs"),
+            is_crate_root: true,
+        };
+        let values = std::iter::once(("CFG_RELEASE".to_owned(), "1.41.0-nightly".to_owned()));
+        let mut content = "let a = env!(\"CFG_RELEASE\");\n\
+                           let b = option_env!(\"CFG_RELEASE\");\n\
+                           let c = env!(\"CFG_UNKNOWN\");\n"
+            .to_owned();
+        let changes = ReplaceCfgEnv::new(values.collect())
+            .transform(&file, &mut content)
+            .unwrap();
+        assert_eq!(
+            content,
+            "let a = \"1.41.0-nightly\";\n\
+             let b = Some(\"1.41.0-nightly\");\n\
+             let c = env!(\"CFG_UNKNOWN\");\n"
+        );
+        assert_eq!(
+            changes,
+            [
+                "replaced `env!(\"CFG_RELEASE\")` with `\"1.41.0-nightly\"` at line 1",
+                "replaced `option_env!(\"CFG_RELEASE\")` with `Some(\"1.41.0-nightly\")` at line 2",
+                "left `env!(\"CFG_UNKNOWN\")` at line 3 alone, its value is unknown",
+            ]
+        );
+    }
+
+    #[test]
+    fn generates_build_scripts() {
+        let values = std::iter::once(("CFG_RELEASE".to_owned(), "1.41.0".to_owned())).collect();
+        let names = ["CFG_RELEASE", "CFG_UNKNOWN"].iter().copied().collect();
+        assert_eq!(
+            build_script(&names, &values),
+            "// Generated by rustc-publisher: sets the environment variables that the upstream \
+             build system sets.\n\nfn main() {\n\
+             \x20   println!(\"cargo:rustc-env=CFG_RELEASE={}\", \"1.41.0\");\n}\n"
+        );
+    }
+
+    #[test]
+    fn computes_values_from_the_checkout() {
+        let workspace = Workspace::new(&[("src/version", "1.41.0\n")]);
+        workspace.commit();
+        let hash = version::git(workspace.root(), &["rev-parse", "--short=9", "HEAD"]).unwrap();
+
+        let nightly = values(workspace.root(), "nightly").unwrap();
+        assert_eq!(nightly["CFG_RELEASE"], "1.41.0-nightly");
+        assert_eq!(nightly["CFG_RELEASE_NUM"], "1.41.0");
+        assert_eq!(nightly["CFG_RELEASE_CHANNEL"], "nightly");
+        assert_eq!(nightly["CFG_VER_HASH"], hash);
+        assert_eq!(
+            nightly["CFG_VERSION"],
+            format!("1.41.0-nightly ({} {})", hash, nightly["CFG_VER_DATE"])
+        );
+        let stable = values(workspace.root(), "stable").unwrap();
+        assert_eq!(stable["CFG_RELEASE"], "1.41.0");
+    }
+}
@@ -0,0 +1,215 @@
+//! The configuration file, `publisher.toml`.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use cargo_metadata::DependencyKind;
+use serde::Deserialize;
+
+use crate::{cfg_env, package_metadata::PackageMetadata, patch};
+
+pub const DEFAULT_CONFIG_PATH: &str = "publisher.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Path to the upstream rust checkout.
+    pub root: PathBuf,
+    /// Directory the crates are copied to.
+    pub out: PathBuf,
+    /// Members of the upstream workspace to load, e.g., `src/rustc`, instead of those listed in
+    /// its manifest. The upstream checkout is never modified.
+    pub workspace_members: Vec<String>,
+    /// Crates to copy along with their local dependencies, e.g., `libsyntax`.
+    pub roots: Vec<String>,
+    /// Kinds of dependencies that pull local crates into the copied set. Dependencies of the
+    /// other kinds on crates that are not copied are removed from the copied manifests.
+    pub dependency_kinds: Vec<DependencyKind>,
+    /// Template of the name of every copied crate. `{name}` is replaced with the upstream name.
+    pub name_template: String,
+    /// Template of the version of every copied crate. `{commits}`, `{release}` and `{date}` are
+    /// replaced with the number of upstream commits, the content of `src/version` and the
+    /// upstream commit date respectively.
+    pub version_template: String,
+    /// Value `cfg(bootstrap)` is resolved to in the copied sources, removing the dead items and
+    /// attributes. The sources are left alone when unset.
+    pub bootstrap: Option<bool>,
+    /// Release channel the `CFG_*` environment variables are computed for, e.g., `nightly`.
+    pub channel: String,
+    /// How the `CFG_*` environment variables read by the sources are provided, `literal` or
+    /// `build-script`.
+    pub cfg_env: cfg_env::Mode,
+    /// Directory of the patch series, `<patches>/<crate>/*.patch`, applied to the copied crates.
+    pub patches: PathBuf,
+    /// What to do when a patch does not apply, `fail` or `skip`.
+    pub on_patch_conflict: patch::OnConflict,
+    /// Whether to vendor the registry dependencies of the copied crates into the output, so that
+    /// it builds offline. They are copied from the upstream `vendor/` directory if there is one.
+    pub vendor: bool,
+    /// `[package]` fields crates.io needs, set in every copied crate that lacks them.
+    pub package: PackageMetadata,
+    /// Per-crate settings, keyed by the upstream package name.
+    pub crates: BTreeMap<String, CrateConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CrateConfig {
+    /// Whether to add `#![feature(rustc_private)]` to the crate root. By default, it is added when
+    /// the sources link to a crate that only the sysroot provides.
+    pub rustc_private: Option<bool>,
+    /// Name of the copied crate, instead of the one generated from `name-template`.
+    pub name: Option<String>,
+    /// Files or directories not to copy, relative to the crate root.
+    pub exclude: Vec<PathBuf>,
+    /// Paths of attributes to remove from the sources, e.g., `rustc_diagnostic_item`.
+    pub strip_attributes: Vec<String>,
+    /// Text substitutions applied to the sources, in order.
+    pub replace: Vec<Replacement>,
+    /// How the `CFG_*` environment variables are provided, instead of the top-level `cfg-env`.
+    pub cfg_env: Option<cfg_env::Mode>,
+    /// `[package]` fields set in the crate even if upstream sets them, overriding those of the
+    /// top-level `package`.
+    pub package: PackageMetadata,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Replacement {
+    pub from: String,
+    pub to: String,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            root: PathBuf::from("rust-src"),
+            out: PathBuf::from("rustfmt-syntax"),
+            workspace_members: vec![],
+            roots: vec![],
+            dependency_kinds: vec![DependencyKind::Normal, DependencyKind::Build],
+            name_template: "rustfmt-{name}".to_owned(),
+            version_template: "{commits}.0.0+{release}.{date}".to_owned(),
+            bootstrap: None,
+            channel: "nightly".to_owned(),
+            cfg_env: cfg_env::Mode::Literal,
+            patches: PathBuf::from("patches"),
+            on_patch_conflict: patch::OnConflict::Fail,
+            vendor: false,
+            package: PackageMetadata::default(),
+            crates: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file at `path`.
+    ///
+    /// When no path is given, `publisher.toml` in the current directory is used if it exists.
+    pub fn load(path: Option<&Path>) -> io::Result<Config> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Path::new(DEFAULT_CONFIG_PATH),
+            None => return Ok(Config::default()),
+        };
+        debug!("loading the configuration from {:?}", path);
+
+        let content = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse {:?}: {}", path, e),
+            )
+        })?;
+        if config.dependency_kinds.contains(&DependencyKind::Unknown) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Failed to parse {:?}: dependency kinds must be `normal`, `build` or `dev`",
+                    path
+                ),
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn krate(&self, name: &str) -> Option<&CrateConfig> {
+        self.crates.get(name)
+    }
+
+    /// Returns how the `CFG_*` environment variables are provided to the crate named `name`.
+    pub fn cfg_env(&self, name: &str) -> cfg_env::Mode {
+        self.krate(name)
+            .and_then(|c| c.cfg_env)
+            .unwrap_or(self.cfg_env)
+    }
+}
+
+/// Parses a dependency kind as written in the configuration.
+pub fn parse_dependency_kind(kind: &str) -> Result<DependencyKind, String> {
+    match kind {
+        "normal" => Ok(DependencyKind::Normal),
+        "build" => Ok(DependencyKind::Build),
+        "dev" => Ok(DependencyKind::Development),
+        _ => Err(format!(
+            "Unknown dependency kind `{}`: expected `normal`, `build` or `dev`",
+            kind
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    fn load(content: &str) -> io::Result<Config> {
+        let workspace = Workspace::new(&[("publisher.toml", content)]);
+        Config::load(Some(&workspace.root().join("publisher.toml")))
+    }
+
+    #[test]
+    fn loads_the_example_configuration() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_CONFIG_PATH);
+        Config::load(Some(&path)).unwrap();
+    }
+
+    #[test]
+    fn parses_per_crate_settings() {
+        let config = load(
+            "root = \"rust\"\nroots = [\"libsyntax\"]\n\n\
+             [crates.syntax]\nname = \"rustfmt-libsyntax\"\nexclude = [\"tests\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.root, Path::new("rust"));
+        assert_eq!(config.roots, ["libsyntax"]);
+        // Unset keys keep their default.
+        assert_eq!(config.name_template, "rustfmt-{name}");
+        let syntax = config.krate("syntax").unwrap();
+        assert_eq!(syntax.name.as_deref(), Some("rustfmt-libsyntax"));
+        assert_eq!(syntax.exclude, [Path::new("tests")]);
+        assert!(config.krate("syntax_pos").is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(load("rots = [\"libsyntax\"]\n").is_err());
+        assert!(load("[crates.syntax]\nrename = \"rustfmt-libsyntax\"\n").is_err());
+    }
+
+    #[test]
+    fn parses_dependency_kinds() {
+        let config = load("dependency-kinds = [\"normal\", \"dev\"]\n").unwrap();
+        assert_eq!(
+            config.dependency_kinds,
+            [DependencyKind::Normal, DependencyKind::Development]
+        );
+        assert!(load("dependency-kinds = [\"test\"]\n").is_err());
+
+        assert_eq!(parse_dependency_kind("build"), Ok(DependencyKind::Build));
+        assert!(parse_dependency_kind("development").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! Parsing the header of a crate root: the shebang, comments, doc comments and inner attributes
+//! that come before the first item.
+
+use std::ops::Range;
+
+/// The header of a crate root.
+#[derive(Debug)]
+pub struct Header<'a> {
+    source: &'a str,
+    /// The leading inner attributes, e.g., `#![feature(rustc_private)]`, in order.
+    pub attributes: Vec<InnerAttribute<'a>>,
+    /// Where a new inner attribute goes: after the last one, or after the shebang, license
+    /// header and doc comments if there is none.
+    pub insertion_point: usize,
+}
+
+#[derive(Debug)]
+pub struct InnerAttribute<'a> {
+    /// Byte range of the whole attribute, from `#![` to `]`.
+    pub span: Range<usize>,
+    /// The content between `#![` and `]`, e.g., `feature(rustc_private)`.
+    pub content: &'a str,
+}
+
+impl<'a> Header<'a> {
+    pub fn parse(source: &'a str) -> Header<'a> {
+        let mut attributes = vec![];
+        let mut pos = 0;
+        if source.starts_with('\u{feff}') {
+            pos = '\u{feff}'.len_utf8();
+        }
+        if source[pos..].starts_with("#!") && !source[pos..].starts_with("#![") {
+            pos = next_line(source, pos);
+        }
+        let mut insertion_point = pos;
+
+        loop {
+            let start = pos + (source.len() - pos - source[pos..].trim_start().len());
+            let rest = &source[start..];
+            let end = if is_outer_doc_comment(rest) {
+                // The doc comment of the first item.
+                break;
+            } else if rest.starts_with("//") {
+                source[start..]
+                    .find('\n')
+                    .map_or(source.len(), |i| start + i)
+            } else if rest.starts_with("/*") {
+                match block_comment_end(source, start) {
+                    Some(end) => end,
+                    None => break,
+                }
+            } else if rest.starts_with("#![") {
+                match attribute_end(source, start) {
+                    Some(end) => {
+                        attributes.push(InnerAttribute {
+                            span: start..end,
+                            content: source[start + 3..end - 1].trim(),
+                        });
+                        end
+                    }
+                    None => break,
+                }
+            } else {
+                break;
+            };
+
+            // Comments after the last attribute belong to the first item.
+            if rest.starts_with("#![") || attributes.is_empty() {
+                insertion_point = end_of_line(source, end);
+            }
+            pos = end;
+        }
+
+        Header {
+            source,
+            attributes,
+            insertion_point,
+        }
+    }
+
+    /// Returns the 1-based line `offset` is on.
+    pub fn line(&self, offset: usize) -> usize {
+        self.source[..offset].matches('\n').count() + 1
+    }
+
+    /// Returns the `#![feature(...)]` attributes along with the features they enable.
+    pub fn features(&self) -> impl Iterator<Item = (&InnerAttribute<'a>, Vec<&'a str>)> {
+        self.attributes
+            .iter()
+            .filter_map(|attribute| Some((attribute, feature_list(attribute.content)?)))
+    }
+
+    /// Returns whether `feature` is enabled unconditionally.
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features()
+            .any(|(_, features)| features.contains(&feature))
+    }
+}
+
+/// Parses the features out of the content of a `feature(...)` attribute.
+pub fn feature_list(content: &str) -> Option<Vec<&str>> {
+    let list = content
+        .strip_prefix("feature")?
+        .trim_start()
+        .strip_prefix('(')?
+        .strip_suffix(')')?;
+    Some(
+        list.split(',')
+            .map(str::trim)
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+    )
+}
+
+/// Splits the content of a `cfg_attr(predicate, attributes...)` attribute into the predicate and
+/// the attributes.
+pub fn cfg_attr(content: &str) -> Option<(&str, Vec<&str>)> {
+    let arguments = content
+        .strip_prefix("cfg_attr")?
+        .trim_start()
+        .strip_prefix('(')?
+        .strip_suffix(')')?;
+    let mut arguments = split_arguments(arguments).into_iter();
+    let predicate = arguments.next()?;
+    Some((predicate, arguments.collect()))
+}
+
+/// Splits a comma-separated list on the commas that are not nested in parentheses.
+pub fn split_arguments(list: &str) -> Vec<&str> {
+    let mut arguments = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                arguments.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    arguments.push(list[start..].trim());
+    arguments.retain(|argument| !argument.is_empty());
+    arguments
+}
+
+/// Returns whether `rest` starts with an outer doc comment, `///` or `/**`, which documents the
+/// item after it, unlike `////` or `/***`.
+fn is_outer_doc_comment(rest: &str) -> bool {
+    (rest.starts_with("///") && !rest.starts_with("////"))
+        || (rest.starts_with("/**") && !rest.starts_with("/***") && !rest.starts_with("/**/"))
+}
+
+/// Returns the offset after the end of the line `pos` is on.
+fn next_line(source: &str, pos: usize) -> usize {
+    source[pos..]
+        .find('\n')
+        .map_or(source.len(), |i| pos + i + 1)
+}
+
+/// Returns the offset of the next line if nothing but whitespace follows `pos` on its line.
+fn end_of_line(source: &str, pos: usize) -> usize {
+    let next = next_line(source, pos);
+    if source[pos..next].trim().is_empty() {
+        next
+    } else {
+        pos
+    }
+}
+
+/// Returns the offset after the end of the block comment at `start`, which may be nested.
+fn block_comment_end(source: &str, start: usize) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut depth = 0;
+    let mut i = start;
+    while i + 1 < bytes.len() {
+        match (bytes[i], bytes[i + 1]) {
+            (b'/', b'*') => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', b'/') => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Returns the offset after the `]` closing the inner or outer attribute at `start`, skipping
+/// string literals.
+pub fn attribute_end(source: &str, start: usize) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut i = start + source[start..].find('[')?;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if in_string => i += 1,
+            b'"' => in_string = !in_string,
+            b'[' if !in_string => depth += 1,
+            b']' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_bom_shebang_and_comments() {
+        let source = "\u{feff}#!/usr/bin/env rust\n// License.\n/* Block. */\n//! Docs.\n\
+                      #![feature(a)]\n\nfn foo() {}\n";
+        let header = Header::parse(source);
+        assert_eq!(header.attributes.len(), 1);
+        assert_eq!(header.attributes[0].content, "feature(a)");
+        assert_eq!(&source[header.insertion_point..], "\nfn foo() {}\n");
+    }
+
+    #[test]
+    fn stops_at_outer_doc_comments() {
+        for source in &[
+            "/// Docs of foo.\npub fn foo() {}\n",
+            "/** Docs of foo. */\npub fn foo() {}\n",
+        ] {
+            let header = Header::parse(source);
+            assert!(header.attributes.is_empty());
+            assert_eq!(header.insertion_point, 0);
+        }
+
+        let source = "//! Docs.\n//// Not docs.\n/// Docs of foo.\n#![feature(a)]\n";
+        let header = Header::parse(source);
+        assert!(header.attributes.is_empty());
+        assert_eq!(
+            &source[header.insertion_point..],
+            "/// Docs of foo.\n#![feature(a)]\n"
+        );
+    }
+
+    #[test]
+    fn parses_multi_line_feature_lists() {
+        let header = Header::parse("#![feature(\n    a,\n    b,\n)]\n");
+        assert!(header.has_feature("a"));
+        assert!(header.has_feature("b"));
+        assert!(!header.has_feature("c"));
+    }
+}
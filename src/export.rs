@@ -0,0 +1,180 @@
+//! Exporting the dependency graph of the crates to copy.
+
+use std::{collections::BTreeSet, fmt::Write, io, str::FromStr};
+
+use serde_json::json;
+
+use crate::graph::{kind_name, CrateGraph, Edge, LocalCrate};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Dot,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "dot" => Ok(Format::Dot),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown format `{}`: expected `dot` or `json`", s)),
+        }
+    }
+}
+
+/// Formats the dependencies between `crates` in the given format.
+pub fn export(
+    graph: &CrateGraph<'_>,
+    crates: &BTreeSet<LocalCrate<'_>>,
+    format: Format,
+) -> io::Result<String> {
+    match format {
+        Format::Dot => Ok(to_dot(graph, crates)),
+        Format::Json => serde_json::to_string_pretty(&to_json(graph, crates))
+            .map(|json| json + "\n")
+            .map_err(io::Error::other),
+    }
+}
+
+/// Returns every dependency between two of `crates`.
+fn edges<'a, 'b>(
+    graph: &'b CrateGraph<'a>,
+    crates: &'b BTreeSet<LocalCrate<'a>>,
+) -> impl Iterator<Item = (&'b LocalCrate<'a>, &'b Edge<'a>)> {
+    crates.iter().flat_map(move |krate| {
+        graph
+            .dependencies(krate)
+            .iter()
+            .filter(move |edge| crates.iter().any(|c| c.name == edge.to))
+            .map(move |edge| (krate, edge))
+    })
+}
+
+fn to_dot(graph: &CrateGraph<'_>, crates: &BTreeSet<LocalCrate<'_>>) -> String {
+    let mut dot = "digraph crates {\n".to_owned();
+    for krate in crates {
+        writeln!(dot, "    {:?};", krate.name).unwrap();
+    }
+    for (krate, edge) in edges(graph, crates) {
+        let label = match &edge.target {
+            Some(target) => format!("{} ({})", kind_name(edge.kind), target),
+            None => kind_name(edge.kind).to_owned(),
+        };
+        writeln!(
+            dot,
+            "    {:?} -> {:?} [label={:?}];",
+            krate.name, edge.to, label
+        )
+        .unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn to_json(graph: &CrateGraph<'_>, crates: &BTreeSet<LocalCrate<'_>>) -> serde_json::Value {
+    let nodes: Vec<_> = crates
+        .iter()
+        .map(|krate| {
+            json!({
+                "name": krate.name,
+                "root_path": krate.root_path,
+                "lib_path": krate.lib_path(),
+            })
+        })
+        .collect();
+    let edges: Vec<_> = edges(graph, crates)
+        .map(|(krate, edge)| {
+            json!({
+                "from": krate.name,
+                "to": edge.to,
+                "kind": kind_name(edge.kind),
+                "target": edge.target,
+            })
+        })
+        .collect();
+
+    json!({ "nodes": nodes, "edges": edges })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    fn workspace() -> Workspace {
+        Workspace::new(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n",
+            ),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\n\n\
+                 [dependencies]\nc = { path = \"../c\" }\n\n\
+                 [target.'cfg(windows)'.build-dependencies]\nb = { path = \"../b\" }\n",
+            ),
+            ("a/src/lib.rs", ""),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.0.0\"\n",
+            ),
+            ("b/src/lib.rs", ""),
+            (
+                "c/Cargo.toml",
+                "[package]\nname = \"c\"\nversion = \"0.0.0\"\n",
+            ),
+            ("c/src/lib.rs", ""),
+        ])
+    }
+
+    #[test]
+    fn exports_dot() {
+        let workspace = workspace();
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        // Only the dependencies between the given crates are exported.
+        let crates = ["a", "b"]
+            .iter()
+            .map(|name| graph.find(name).unwrap())
+            .collect();
+
+        assert_eq!(
+            export(&graph, &crates, Format::Dot).unwrap(),
+            "digraph crates {\n    \"a\";\n    \"b\";\n    \
+             \"a\" -> \"b\" [label=\"build (cfg(windows))\"];\n}\n"
+        );
+    }
+
+    #[test]
+    fn exports_json() {
+        let workspace = workspace();
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let crates = graph.crates().collect();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&export(&graph, &crates, Format::Json).unwrap()).unwrap();
+        let c = graph.find("c").unwrap();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            json["nodes"][2],
+            json!({ "name": "c", "root_path": c.root_path, "lib_path": c.lib_path() })
+        );
+        assert_eq!(
+            json["edges"],
+            json!([
+                { "from": "a", "to": "c", "kind": "normal", "target": null },
+                { "from": "a", "to": "b", "kind": "build", "target": "cfg(windows)" },
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert!(matches!("dot".parse(), Ok(Format::Dot)));
+        assert!(matches!("json".parse(), Ok(Format::Json)));
+        assert!("svg".parse::<Format>().is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! The unstable features the copied crates enable, checked against the feature tables of the
+//! upstream compiler.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::Path,
+};
+
+use crate::{
+    crate_root::{self, Header},
+    graph::LocalCrate,
+};
+
+/// Directory of the feature tables, relative to the upstream checkout.
+const FEATURE_TABLES: &str = "src/librustc_feature";
+
+/// The state of a language feature in the upstream compiler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Still unstable, since the given version.
+    Active(String),
+    /// Stabilized in the given version.
+    Accepted(String),
+    /// Removed in the given version.
+    Removed(String),
+    /// Not a language feature of the upstream compiler, e.g., a library feature.
+    Unknown,
+}
+
+impl Status {
+    /// Whether enabling the feature is a mistake, because it is stable or gone.
+    pub fn is_stale(&self) -> bool {
+        match self {
+            Status::Accepted(_) | Status::Removed(_) => true,
+            Status::Active(_) | Status::Unknown => false,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Active(since) => write!(f, "unstable since {}", since),
+            Status::Accepted(since) => write!(f, "stabilized in {}", since),
+            Status::Removed(since) => write!(f, "removed in {}", since),
+            Status::Unknown => write!(f, "not a language feature"),
+        }
+    }
+}
+
+/// A feature enabled in the root of a crate.
+#[derive(Debug)]
+pub struct Feature {
+    pub name: String,
+    /// The `cfg_attr` predicate the feature is enabled under, e.g., `bootstrap`.
+    pub condition: Option<String>,
+    pub line: usize,
+    pub status: Status,
+}
+
+/// The language features known to the upstream compiler, keyed by name.
+#[derive(Debug)]
+pub struct FeatureTables {
+    features: BTreeMap<String, Status>,
+}
+
+impl FeatureTables {
+    /// Reads the `declare_features!` tables of the upstream checkout at `root`.
+    pub fn load(root: &Path) -> io::Result<FeatureTables> {
+        let dir = root.join(FEATURE_TABLES);
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Could not find the feature tables in {:?}", dir),
+            ));
+        }
+
+        let mut features = BTreeMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "rs") {
+                continue;
+            }
+            for line in fs::read_to_string(&path)?.lines() {
+                if let Some((name, status)) = parse_declaration(line) {
+                    features.insert(name.to_owned(), status);
+                }
+            }
+        }
+        debug!("found {} features in {:?}", features.len(), dir);
+        Ok(FeatureTables { features })
+    }
+
+    pub fn status(&self, feature: &str) -> Status {
+        self.features
+            .get(feature)
+            .cloned()
+            .unwrap_or(Status::Unknown)
+    }
+}
+
+/// Returns the features enabled in the root of `krate`, either unconditionally or through
+/// `#![cfg_attr(..., feature(...))]`.
+pub fn crate_features(krate: &LocalCrate<'_>, tables: &FeatureTables) -> io::Result<Vec<Feature>> {
+    let source = fs::read_to_string(krate.lib_path())?;
+    let header = Header::parse(&source);
+
+    let mut features = vec![];
+    for attribute in &header.attributes {
+        let line = header.line(attribute.span.start);
+        let mut push = |names: Vec<&str>, condition: Option<&str>| {
+            for name in names {
+                features.push(Feature {
+                    name: name.to_owned(),
+                    condition: condition.map(ToOwned::to_owned),
+                    line,
+                    status: tables.status(name),
+                });
+            }
+        };
+
+        if let Some(names) = crate_root::feature_list(attribute.content) {
+            push(names, None);
+        } else if let Some((condition, attributes)) = crate_root::cfg_attr(attribute.content) {
+            for attribute in attributes {
+                if let Some(names) = crate_root::feature_list(attribute) {
+                    push(names, Some(condition));
+                }
+            }
+        }
+    }
+    Ok(features)
+}
+
+/// Describes the features of every crate in `crates`.
+pub fn report(crates: &BTreeSet<LocalCrate<'_>>, tables: &FeatureTables) -> io::Result<String> {
+    let mut report = String::new();
+    let mut stale = 0;
+    for krate in crates {
+        let features = crate_features(krate, tables)?;
+        report.push_str(&format!(
+            "{} ({})\n",
+            krate.name,
+            krate.lib_path().display()
+        ));
+        if features.is_empty() {
+            report.push_str("  no features\n");
+        }
+        for feature in features {
+            let condition = match &feature.condition {
+                Some(condition) => format!(" if cfg({})", condition),
+                None => String::new(),
+            };
+            let marker = if feature.status.is_stale() {
+                stale += 1;
+                "!"
+            } else {
+                " "
+            };
+            report.push_str(&format!(
+                "{} {}{} at line {}: {}\n",
+                marker, feature.name, condition, feature.line, feature.status
+            ));
+        }
+    }
+    if stale > 0 {
+        report.push_str(&format!(
+            "\n{} feature(s) marked with `!` are stabilized or removed upstream\n",
+            stale
+        ));
+    }
+    Ok(report)
+}
+
+/// Parses a line of `declare_features!`, e.g., `(active, box_syntax, "1.0.0", Some(49733), None),`.
+fn parse_declaration(line: &str) -> Option<(&str, Status)> {
+    let mut fields = line.trim().strip_prefix('(')?.split(',').map(str::trim);
+    let kind = fields.next()?;
+    let name = fields.next()?;
+    let since = fields.next()?.trim_matches('"').to_owned();
+    let status = match kind {
+        "active" => Status::Active(since),
+        "accepted" => Status::Accepted(since),
+        "removed" | "stable_removed" => Status::Removed(since),
+        _ => return None,
+    };
+    Some((name, status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::CrateGraph, testing::Workspace};
+
+    #[test]
+    fn parses_declarations() {
+        assert_eq!(
+            parse_declaration("    (active, box_syntax, \"1.0.0\", Some(49733), None),"),
+            Some(("box_syntax", Status::Active("1.0.0".to_owned())))
+        );
+        assert_eq!(
+            parse_declaration("(accepted, slice_patterns, \"1.42.0\", Some(62254), None),"),
+            Some(("slice_patterns", Status::Accepted("1.42.0".to_owned())))
+        );
+        assert_eq!(
+            parse_declaration("(stable_removed, no_stack_check, \"1.0.0\", None, None),"),
+            Some(("no_stack_check", Status::Removed("1.0.0".to_owned())))
+        );
+        assert_eq!(
+            parse_declaration("/// (active, doc, \"1.0.0\", None, None),"),
+            None
+        );
+        assert_eq!(parse_declaration("declare_features! ("), None);
+    }
+
+    #[test]
+    fn reports_the_features_of_every_crate() {
+        let workspace = Workspace::new(&[
+            (
+                "src/librustc_feature/active.rs",
+                "declare_features! (\n    \
+                 (active, box_syntax, \"1.0.0\", Some(49733), None),\n);\n",
+            ),
+            (
+                "src/librustc_feature/accepted.rs",
+                "declare_features! (\n    \
+                 (accepted, slice_patterns, \"1.42.0\", None, None),\n);\n",
+            ),
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\n",
+            ),
+            (
+                "a/src/lib.rs",
+                "//! Docs.\n#![feature(box_syntax,\n    slice_patterns)]\n\
+                 #![cfg_attr(bootstrap, feature(const_fn))]\n\nfn f() {}\n",
+            ),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.0.0\"\n",
+            ),
+            ("b/src/lib.rs", ""),
+        ]);
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let crates = graph.crates().collect();
+        let tables = FeatureTables::load(workspace.root()).unwrap();
+
+        let report = report(&crates, &tables).unwrap();
+        let a = graph.find("a").unwrap();
+        let b = graph.find("b").unwrap();
+        assert_eq!(
+            report,
+            format!(
+                "a ({})\n\
+                 \x20 box_syntax at line 2: unstable since 1.0.0\n\
+                 ! slice_patterns at line 2: stabilized in 1.42.0\n\
+                 \x20 const_fn if cfg(bootstrap) at line 4: not a language feature\n\
+                 b ({})\n\
+                 \x20 no features\n\
+                 \n1 feature(s) marked with `!` are stabilized or removed upstream\n",
+                a.lib_path().display(),
+                b.lib_path().display()
+            )
+        );
+    }
+}
@@ -0,0 +1,506 @@
+//! The dependency graph of the local crates in the upstream workspace.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+};
+
+use cargo_metadata::{DependencyKind, Metadata, Package, Target};
+
+/// Target kinds that make a target the library of its package.
+const LIB_KINDS: &[&str] = &["lib", "rlib", "dylib", "proc-macro"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalCrate<'a> {
+    pub name: &'a str,
+    pub root_path: &'a Path,
+    /// Every target of the crate, including binaries, tests and build scripts.
+    pub targets: &'a [Target],
+}
+
+impl<'a> LocalCrate<'a> {
+    pub fn lib_target(&self) -> Option<&'a Target> {
+        self.targets.iter().find(|target| {
+            target
+                .kind
+                .iter()
+                .any(|kind| LIB_KINDS.contains(&kind.as_str()))
+        })
+    }
+
+    /// Returns the root of the library target.
+    ///
+    /// Every crate in `CrateGraph::closure` has a library target.
+    pub fn lib_path(&self) -> &'a Path {
+        match self.lib_target() {
+            Some(target) => &target.src_path,
+            None => panic!("{} has no library target", self.name),
+        }
+    }
+
+    /// Returns the name of the directory this crate lives in, which is kept in the output.
+    pub fn dir_name(&self) -> &OsStr {
+        self.root_path.file_name().unwrap()
+    }
+
+    pub fn out_dir(&self, out: &Path) -> PathBuf {
+        out.join(self.dir_name())
+    }
+}
+
+// Package names are unique within a workspace, so they identify crates.
+impl PartialEq for LocalCrate<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for LocalCrate<'_> {}
+
+impl PartialOrd for LocalCrate<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LocalCrate<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.name.cmp(other.name)
+    }
+}
+
+/// A dependency of a local crate on another local crate.
+#[derive(Debug, Clone)]
+pub struct Edge<'a> {
+    /// Name of the package depended on.
+    pub to: &'a str,
+    pub kind: DependencyKind,
+    /// The platform this dependency is specific to, e.g., `cfg(windows)`.
+    pub target: Option<String>,
+}
+
+/// The local crates and the dependencies between them, built once from `cargo metadata`.
+#[derive(Debug)]
+pub struct CrateGraph<'a> {
+    packages: BTreeMap<&'a str, &'a Package>,
+    crates: BTreeMap<&'a str, LocalCrate<'a>>,
+    edges: BTreeMap<&'a str, Vec<Edge<'a>>>,
+}
+
+impl<'a> CrateGraph<'a> {
+    pub fn new(metadata: &'a Metadata) -> CrateGraph<'a> {
+        let mut graph = CrateGraph {
+            packages: BTreeMap::new(),
+            crates: BTreeMap::new(),
+            edges: BTreeMap::new(),
+        };
+
+        for package in &metadata.packages {
+            let krate = LocalCrate {
+                name: package.name.as_str(),
+                root_path: package
+                    .manifest_path
+                    .parent()
+                    .expect("Manifest path's parent directory does not exist"),
+                targets: &package.targets,
+            };
+            let edges = package
+                .dependencies
+                .iter()
+                .filter(|dep| dep.source.is_none())
+                .map(|dep| Edge {
+                    to: dep.name.as_str(),
+                    kind: dep.kind,
+                    target: dep.target.as_ref().map(ToString::to_string),
+                })
+                .collect();
+
+            graph.packages.insert(krate.name, package);
+            graph.crates.insert(krate.name, krate);
+            graph.edges.insert(krate.name, edges);
+        }
+
+        graph
+    }
+
+    /// Finds a crate either by its name or by the name of its directory, e.g., `libsyntax`.
+    pub fn find(&self, name: &str) -> io::Result<LocalCrate<'a>> {
+        self.crates
+            .get(name)
+            .or_else(|| {
+                self.crates
+                    .values()
+                    .find(|krate| format!("lib{}", krate.name) == name)
+            })
+            .copied()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("Could not find {}", name))
+            })
+    }
+
+    /// Returns every local crate, including those without a library target.
+    pub fn crates(&self) -> impl Iterator<Item = LocalCrate<'a>> + '_ {
+        self.crates.values().copied()
+    }
+
+    pub fn package(&self, krate: &LocalCrate<'_>) -> &'a Package {
+        self.packages[krate.name]
+    }
+
+    pub fn dependencies(&self, krate: &LocalCrate<'_>) -> &[Edge<'a>] {
+        &self.edges[krate.name]
+    }
+
+    /// Returns the given crates and every local crate they transitively depend on through
+    /// dependencies of the given kinds.
+    pub fn closure<S: AsRef<str>>(
+        &self,
+        roots: &[S],
+        kinds: &[DependencyKind],
+    ) -> io::Result<BTreeSet<LocalCrate<'a>>> {
+        let mut visited = BTreeSet::new();
+        let mut queue = roots
+            .iter()
+            .map(|root| self.find(root.as_ref()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        while let Some(krate) = queue.pop() {
+            if krate.lib_target().is_none() {
+                let targets: Vec<_> = krate
+                    .targets
+                    .iter()
+                    .map(|target| format!("{} `{}`", target.kind.join("/"), target.name))
+                    .collect();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} has no library target to copy (targets: {})",
+                        krate.name,
+                        targets.join(", ")
+                    ),
+                ));
+            }
+            if !visited.insert(krate) {
+                continue;
+            }
+            for edge in self.dependencies(&krate) {
+                if kinds.contains(&edge.kind) {
+                    queue.push(self.find(edge.to)?);
+                }
+            }
+        }
+
+        Ok(visited)
+    }
+
+    /// Returns every path from one of the given crates to `target` through dependencies of the
+    /// given kinds, as the root followed by the dependencies taken.
+    ///
+    /// A path never visits the same crate twice, so cycles do not make the search loop forever.
+    pub fn paths_to<S: AsRef<str>>(
+        &self,
+        roots: &[S],
+        target: &str,
+        kinds: &[DependencyKind],
+    ) -> io::Result<Vec<(LocalCrate<'a>, Vec<&Edge<'a>>)>> {
+        let target = self.find(target)?;
+        let mut paths = vec![];
+        for root in roots {
+            let root = self.find(root.as_ref())?;
+            let mut path = PathSearch {
+                target,
+                kinds,
+                crates: vec![root.name],
+                edges: vec![],
+            };
+            for edges in self.collect_paths(root, &mut path)? {
+                paths.push((root, edges));
+            }
+        }
+        Ok(paths)
+    }
+
+    fn collect_paths<'b>(
+        &'b self,
+        krate: LocalCrate<'a>,
+        search: &mut PathSearch<'a, 'b, '_>,
+    ) -> io::Result<Vec<Vec<&'b Edge<'a>>>> {
+        if krate == search.target {
+            return Ok(vec![search.edges.clone()]);
+        }
+
+        let mut paths = vec![];
+        for edge in self.dependencies(&krate) {
+            if !search.kinds.contains(&edge.kind) || search.crates.contains(&edge.to) {
+                continue;
+            }
+            let dependency = self.find(edge.to)?;
+            search.crates.push(edge.to);
+            search.edges.push(edge);
+            paths.extend(self.collect_paths(dependency, search)?);
+            search.edges.pop();
+            search.crates.pop();
+        }
+        Ok(paths)
+    }
+
+    /// Orders `crates` so that every crate comes after all of its dependencies among `crates`.
+    ///
+    /// Dev-dependencies are ignored since `cargo publish` does not require them to be published.
+    /// Fails with the offending path if the remaining dependencies form a cycle.
+    pub fn leaf_first_order(
+        &self,
+        crates: &BTreeSet<LocalCrate<'a>>,
+    ) -> io::Result<Vec<LocalCrate<'a>>> {
+        let mut done = BTreeSet::new();
+        let mut path = vec![];
+        let mut order = vec![];
+        for krate in crates {
+            self.visit(crates, *krate, &mut done, &mut path, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit(
+        &self,
+        crates: &BTreeSet<LocalCrate<'a>>,
+        krate: LocalCrate<'a>,
+        done: &mut BTreeSet<&'a str>,
+        path: &mut Vec<&'a str>,
+        order: &mut Vec<LocalCrate<'a>>,
+    ) -> io::Result<()> {
+        if done.contains(krate.name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|name| *name == krate.name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(krate.name);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Dependency cycle: {}", cycle.join(" -> ")),
+            ));
+        }
+
+        path.push(krate.name);
+        for edge in self.dependencies(&krate) {
+            if edge.kind == DependencyKind::Development {
+                continue;
+            }
+            if let Some(dependency) = crates.iter().find(|c| c.name == edge.to) {
+                self.visit(crates, *dependency, done, path, order)?;
+            }
+        }
+        path.pop();
+
+        done.insert(krate.name);
+        order.push(krate);
+        Ok(())
+    }
+}
+
+/// The state of a search for the paths to a crate in `CrateGraph::paths_to`.
+struct PathSearch<'a, 'b, 'k> {
+    target: LocalCrate<'a>,
+    kinds: &'k [DependencyKind],
+    /// The crates on the current path, starting from the root.
+    crates: Vec<&'a str>,
+    edges: Vec<&'b Edge<'a>>,
+}
+
+/// Returns the name of a dependency kind as written in `Cargo.toml`'s table names.
+pub fn kind_name(kind: DependencyKind) -> &'static str {
+    match kind {
+        DependencyKind::Normal => "normal",
+        DependencyKind::Development => "dev",
+        DependencyKind::Build => "build",
+        DependencyKind::Unknown => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    fn package(name: &str, dependencies: &str) -> (String, String) {
+        (
+            format!("{}/Cargo.toml", name),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.0.0\"\n\n{}",
+                name, dependencies
+            ),
+        )
+    }
+
+    /// `a` depends on `b`, which depends on `c` at build time. `a` and `d` are each other's
+    /// dev-dependencies, and `x` and `y` depend on each other.
+    fn workspace() -> Workspace {
+        let mut files = vec![
+            package(
+                "a",
+                "[dependencies]\nb = { path = \"../b\" }\n\n\
+                 [dev-dependencies]\nd = { path = \"../d\" }\n",
+            ),
+            package("b", "[build-dependencies]\nc = { path = \"../c\" }\n"),
+            package("c", ""),
+            package("d", "[dev-dependencies]\na = { path = \"../a\" }\n"),
+            package("x", "[dependencies]\ny = { path = \"../y\" }\n"),
+            package("y", "[dependencies]\nx = { path = \"../x\" }\n"),
+        ];
+        for name in &["a", "b", "c", "d", "x", "y"] {
+            files.push((format!("{}/src/lib.rs", name), String::new()));
+        }
+        files.push((
+            "Cargo.toml".to_owned(),
+            "[workspace]\nmembers = [\"a\", \"b\", \"c\", \"d\", \"x\", \"y\"]\n".to_owned(),
+        ));
+        let files: Vec<_> = files
+            .iter()
+            .map(|(path, content)| (path.as_str(), content.as_str()))
+            .collect();
+        Workspace::new(&files)
+    }
+
+    fn names<'a>(crates: &[LocalCrate<'a>]) -> Vec<&'a str> {
+        crates.iter().map(|krate| krate.name).collect()
+    }
+
+    #[test]
+    fn computes_closures() {
+        let workspace = workspace();
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let all = [
+            DependencyKind::Normal,
+            DependencyKind::Build,
+            DependencyKind::Development,
+        ];
+
+        let closure: Vec<_> = graph.closure(&["a"], &all).unwrap().into_iter().collect();
+        assert_eq!(names(&closure), ["a", "b", "c", "d"]);
+        let closure: Vec<_> = graph.closure(&["x"], &all).unwrap().into_iter().collect();
+        assert_eq!(names(&closure), ["x", "y"]);
+        assert!(graph.closure(&["z"], &all).is_err());
+    }
+
+    #[test]
+    fn follows_the_given_dependency_kinds() {
+        let workspace = workspace();
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let closure = |kinds: &[DependencyKind]| -> Vec<_> {
+            names(
+                &graph
+                    .closure(&["a"], kinds)
+                    .unwrap()
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(closure(&[DependencyKind::Normal]), ["a", "b"]);
+        assert_eq!(
+            closure(&[DependencyKind::Normal, DependencyKind::Build]),
+            ["a", "b", "c"]
+        );
+        assert_eq!(closure(&[DependencyKind::Development]), ["a", "d"]);
+    }
+
+    #[test]
+    fn finds_every_path_to_a_crate() {
+        let workspace = workspace();
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let kinds = [DependencyKind::Normal, DependencyKind::Build];
+        let paths = |roots: &[&str], target| -> Vec<String> {
+            graph
+                .paths_to(roots, target, &kinds)
+                .unwrap()
+                .iter()
+                .map(|(root, edges)| {
+                    let mut path = vec![root.name.to_owned()];
+                    path.extend(
+                        edges
+                            .iter()
+                            .map(|edge| format!("{} ({})", edge.to, kind_name(edge.kind))),
+                    );
+                    path.join(" -> ")
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            paths(&["a", "b"], "c"),
+            ["a -> b (normal) -> c (build)", "b -> c (build)"]
+        );
+        assert_eq!(paths(&["a"], "a"), ["a"]);
+        // Only through the given kinds.
+        assert!(paths(&["a"], "d").is_empty());
+        // Without looping through cycles.
+        assert_eq!(paths(&["x"], "y"), ["x -> y (normal)"]);
+        assert!(graph.paths_to(&["a"], "z", &kinds).is_err());
+    }
+
+    #[test]
+    fn selects_the_library_target() {
+        let workspace = Workspace::new(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"tool\", \"macros\"]\n",
+            ),
+            (
+                "tool/Cargo.toml",
+                "[package]\nname = \"tool\"\nversion = \"0.0.0\"\nbuild = \"build.rs\"\n",
+            ),
+            ("tool/build.rs", "fn main() {}\n"),
+            ("tool/src/main.rs", "fn main() {}\n"),
+            (
+                "macros/Cargo.toml",
+                "[package]\nname = \"macros\"\nversion = \"0.0.0\"\n\n\
+                 [lib]\nproc-macro = true\npath = \"macros.rs\"\n\n\
+                 [[bin]]\nname = \"gen\"\npath = \"gen.rs\"\n",
+            ),
+            ("macros/macros.rs", ""),
+            ("macros/gen.rs", "fn main() {}\n"),
+        ]);
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+
+        let macros = graph.find("macros").unwrap();
+        assert_eq!(macros.lib_target().unwrap().kind, ["proc-macro"]);
+        assert!(macros.lib_path().ends_with("macros.rs"));
+        let tool = graph.find("tool").unwrap();
+        assert!(tool.lib_target().is_none());
+        let error = graph
+            .closure(&["tool"], &[DependencyKind::Normal])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "tool has no library target to copy \
+             (targets: bin `tool`, custom-build `build-script-build`)"
+        );
+    }
+
+    #[test]
+    fn orders_crates_leaf_first() {
+        let workspace = workspace();
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let crates = |names: &[&str]| names.iter().map(|name| graph.find(name).unwrap()).collect();
+
+        // Dev-dependencies do not count, so `a` and `d` do not form a cycle.
+        let order = graph
+            .leaf_first_order(&crates(&["a", "b", "c", "d"]))
+            .unwrap();
+        assert_eq!(names(&order), ["c", "b", "a", "d"]);
+        // Only the given crates are ordered.
+        let order = graph.leaf_first_order(&crates(&["a", "c"])).unwrap();
+        assert_eq!(names(&order), ["a", "c"]);
+
+        let error = graph.leaf_first_order(&crates(&["x", "y"])).unwrap_err();
+        assert_eq!(error.to_string(), "Dependency cycle: x -> y -> x");
+    }
+}
@@ -0,0 +1,32 @@
+//! Copies crates out of the rust repository so that they can be published on crates.io.
+//!
+//! The `rustc-publisher` binary drives everything; the library is exposed so that the copy can be
+//! customized, e.g., by adding a `transform::SourceTransform` to the `transform::Pipeline`.
+
+#[macro_use]
+extern crate log;
+
+pub mod cfg;
+pub mod cfg_env;
+pub mod config;
+pub mod crate_root;
+pub mod export;
+pub mod features;
+pub mod graph;
+pub mod lockfile;
+pub mod manifest;
+pub mod normalize;
+pub mod package_metadata;
+pub mod patch;
+pub mod pin;
+pub mod plan;
+pub mod prune;
+pub mod publish;
+pub mod rename;
+pub mod rustc_private;
+#[cfg(test)]
+mod testing;
+pub mod transform;
+pub mod vendor;
+pub mod version;
+pub mod workspace;
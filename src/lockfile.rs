@@ -0,0 +1,259 @@
+//! Pruning the upstream `Cargo.lock` to the external packages the copied crates depend on, so
+//! that they build against the versions upstream tested.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs, io,
+    path::Path,
+};
+
+use toml_edit::{DocumentMut, Item, Table};
+
+/// The upstream lock file, pruned.
+#[derive(Debug)]
+pub struct PrunedLock {
+    /// The content of the pruned lock file.
+    pub content: String,
+    /// The external packages kept.
+    pub packages: Vec<LockedPackage>,
+    /// The number of external packages in the upstream lock file.
+    pub total: usize,
+}
+
+/// A `[[package]]` of a lock file.
+#[derive(Debug)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    /// `None` for the packages of the workspace.
+    pub source: Option<String>,
+    dependencies: Vec<String>,
+}
+
+impl LockedPackage {
+    fn from_table(table: &Table) -> LockedPackage {
+        let str_field = |key| table.get(key).and_then(Item::as_str).map(str::to_owned);
+        LockedPackage {
+            name: str_field("name").unwrap_or_default(),
+            version: str_field("version").unwrap_or_default(),
+            source: str_field("source"),
+            dependencies: table.get("dependencies").and_then(Item::as_array).map_or(
+                vec![],
+                |dependencies| {
+                    dependencies
+                        .iter()
+                        .filter_map(|dependency| dependency.as_str())
+                        .map(str::to_owned)
+                        .collect()
+                },
+            ),
+        }
+    }
+
+    /// Whether the package comes from a registry, e.g., crates.io, rather than git.
+    pub fn is_registry(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|source| source.starts_with("registry+"))
+    }
+
+    /// Whether a dependency, `name [version] [(source)]`, refers to this package.
+    fn matches(&self, dependency: &str) -> bool {
+        let mut parts = dependency.splitn(3, ' ');
+        let name = parts.next().unwrap_or_default();
+        let version = parts.next();
+        let source = parts
+            .next()
+            .map(|source| source.trim_start_matches('(').trim_end_matches(')'));
+        self.name == name
+            && version.is_none_or(|version| self.version == version)
+            && source.is_none_or(|source| self.source.as_deref() == Some(source))
+    }
+
+    /// The key of this package in the `[metadata]` of version 1 lock files.
+    fn checksum_key(&self) -> String {
+        format!(
+            "checksum {} {} ({})",
+            self.name,
+            self.version,
+            self.source.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// Prunes the lock file of the upstream workspace at `root` to the external packages the local
+/// packages named `crates` depend on, directly or not.
+///
+/// The local packages themselves are left out, as they are renamed in the output, so cargo adds
+/// them back. Returns `None` if upstream has no lock file.
+pub fn prune(root: &Path, crates: &BTreeSet<&str>) -> io::Result<Option<PrunedLock>> {
+    let path = root.join("Cargo.lock");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut document = fs::read_to_string(&path)?
+        .parse::<DocumentMut>()
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse {:?}: {}", path, e),
+            )
+        })?;
+
+    let packages: Vec<LockedPackage> = document
+        .get("package")
+        .and_then(Item::as_array_of_tables)
+        .map_or(vec![], |tables| {
+            tables.iter().map(LockedPackage::from_table).collect()
+        });
+
+    let mut reachable = vec![false; packages.len()];
+    let mut queue: VecDeque<usize> = packages
+        .iter()
+        .enumerate()
+        .filter(|(_, package)| package.source.is_none() && crates.contains(package.name.as_str()))
+        .map(|(i, _)| i)
+        .collect();
+    while let Some(i) = queue.pop_front() {
+        if reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        for dependency in &packages[i].dependencies {
+            // Dependencies on local crates that are not copied are removed from the manifests.
+            queue.extend(
+                packages
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, package)| {
+                        package.matches(dependency)
+                            && (package.source.is_some() || crates.contains(package.name.as_str()))
+                    })
+                    .map(|(j, _)| j),
+            );
+        }
+    }
+
+    let keep = |i: usize| reachable[i] && packages[i].source.is_some();
+    if let Some(tables) = document
+        .get_mut("package")
+        .and_then(Item::as_array_of_tables_mut)
+    {
+        // The header comment of the file belongs to the first package.
+        let header = tables
+            .get(0)
+            .and_then(|table| table.decor().prefix())
+            .cloned();
+        let mut i = 0;
+        tables.retain(|_| {
+            i += 1;
+            keep(i - 1)
+        });
+        if let (Some(first), Some(header)) = (tables.get_mut(0), header) {
+            first.decor_mut().set_prefix(header);
+        }
+    }
+    let checksum_keys: BTreeSet<String> = (0..packages.len())
+        .filter(|&i| keep(i))
+        .map(|i| packages[i].checksum_key())
+        .collect();
+    if let Some(metadata) = document.get_mut("metadata").and_then(Item::as_table_mut) {
+        metadata.retain(|key, _| !key.starts_with("checksum ") || checksum_keys.contains(key));
+    }
+
+    let total = packages
+        .iter()
+        .filter(|package| package.source.is_some())
+        .count();
+    Ok(Some(PrunedLock {
+        content: document.to_string(),
+        packages: packages
+            .into_iter()
+            .zip(reachable)
+            .filter(|(package, reachable)| *reachable && package.source.is_some())
+            .map(|(package, _)| package)
+            .collect(),
+        total,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    const REGISTRY: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+    #[test]
+    fn keeps_the_packages_reachable_from_the_crates() {
+        let workspace = Workspace::new(&[(
+            "Cargo.lock",
+            &format!(
+                "# This file is automatically @generated by Cargo.\n\
+                 [[package]]\nname = \"a\"\nversion = \"0.0.0\"\n\
+                 dependencies = [\n \"b\",\n \"log 0.4.8 ({0})\",\n]\n\n\
+                 [[package]]\nname = \"b\"\nversion = \"0.0.0\"\n\
+                 dependencies = [\n \"cfg-if\",\n]\n\n\
+                 [[package]]\nname = \"c\"\nversion = \"0.0.0\"\n\
+                 dependencies = [\n \"a\",\n \"rand\",\n]\n\n\
+                 [[package]]\nname = \"cfg-if\"\nversion = \"0.1.10\"\nsource = \"{0}\"\n\n\
+                 [[package]]\nname = \"log\"\nversion = \"0.4.8\"\nsource = \"{0}\"\n\
+                 dependencies = [\n \"cfg-if\",\n]\n\n\
+                 [[package]]\nname = \"log\"\nversion = \"0.3.9\"\nsource = \"{0}\"\n\n\
+                 [[package]]\nname = \"rand\"\nversion = \"0.7.0\"\nsource = \"{0}\"\n\n\
+                 [metadata]\n\
+                 \"checksum cfg-if 0.1.10 ({0})\" = \"1\"\n\
+                 \"checksum log 0.4.8 ({0})\" = \"2\"\n\
+                 \"checksum log 0.3.9 ({0})\" = \"3\"\n\
+                 \"checksum rand 0.7.0 ({0})\" = \"4\"\n",
+                REGISTRY
+            ),
+        )]);
+        let crates = ["a", "b"].iter().copied().collect();
+
+        let lock = prune(workspace.root(), &crates).unwrap().unwrap();
+        let packages: Vec<_> = lock
+            .packages
+            .iter()
+            .map(|package| format!("{} {}", package.name, package.version))
+            .collect();
+        assert_eq!(packages, ["cfg-if 0.1.10", "log 0.4.8"]);
+        assert_eq!(lock.total, 4);
+        assert_eq!(
+            lock.content,
+            format!(
+                "# This file is automatically @generated by Cargo.\n\
+                 [[package]]\nname = \"cfg-if\"\nversion = \"0.1.10\"\nsource = \"{0}\"\n\n\
+                 [[package]]\nname = \"log\"\nversion = \"0.4.8\"\nsource = \"{0}\"\n\
+                 dependencies = [\n \"cfg-if\",\n]\n\n\
+                 [metadata]\n\
+                 \"checksum cfg-if 0.1.10 ({0})\" = \"1\"\n\
+                 \"checksum log 0.4.8 ({0})\" = \"2\"\n",
+                REGISTRY
+            )
+        );
+    }
+
+    #[test]
+    fn matches_dependencies_by_name_version_and_source() {
+        let package = LockedPackage {
+            name: "log".to_owned(),
+            version: "0.4.8".to_owned(),
+            source: Some(REGISTRY.to_owned()),
+            dependencies: vec![],
+        };
+        assert!(package.is_registry());
+        assert!(package.matches("log"));
+        assert!(package.matches("log 0.4.8"));
+        assert!(package.matches(&format!("log 0.4.8 ({})", REGISTRY)));
+        assert!(!package.matches("log 0.3.9"));
+        assert!(!package.matches("log 0.4.8 (git+https://github.com/rust-lang/log)"));
+        assert!(!package.matches("log-derive"));
+    }
+
+    #[test]
+    fn returns_none_without_a_lock_file() {
+        let workspace = Workspace::new(&[]);
+        assert!(prune(workspace.root(), &BTreeSet::new()).unwrap().is_none());
+    }
+}
@@ -1,139 +1,274 @@
-#[macro_use]
-extern crate log;
-
-use std::{
-    collections::BTreeSet,
-    fs,
-    io::{self, Write},
-    iter::once,
-    path::{Path, PathBuf},
-};
+use std::{io, path::PathBuf};
 
-use cargo_metadata::{Metadata, MetadataCommand};
+use cargo_metadata::DependencyKind;
+use semver::Version;
 use structopt::StructOpt;
-use walkdir::WalkDir;
-use std::fs::create_dir_all;
 
-const CRATES_WHICH_REQUIRES_RUSTC_PRIVATE_FEATURES: &[&str] =
-    &["rustc_data_structures", "rustc_session"];
+use rustc_publisher::{
+    config::{self, Config},
+    export,
+    features::{self, FeatureTables},
+    graph::{self, CrateGraph},
+    plan, publish,
+    transform::Pipeline,
+    version, workspace,
+};
 
 #[derive(Debug, StructOpt)]
-struct Opt {
-    #[structopt(long, default_value = "rust-src", parse(from_os_str))]
-    root: PathBuf,
-    #[structopt(short, long, default_value = "rustfmt-syntax", parse(from_os_str))]
-    out: PathBuf,
-    #[structopt(short, long)]
-    force: bool,
-    #[structopt(name = "CRATE", required = true)]
-    crates: Vec<String>,
+enum Opt {
+    /// Copies the given crates and their local dependencies into a new workspace.
+    Copy(CopyOpt),
+    /// Copies the given crates like `copy`, then publishes every copied crate, leaf-first.
+    Publish(PublishOpt),
+    /// Prints the dependency graph of the crates that would be copied, without copying them.
+    Graph(GraphOpt),
+    /// Prints every dependency path from the given crates to another crate.
+    Why(WhyOpt),
+    /// Prints the version the copied crates would be published with.
+    Version(VersionOpt),
+    /// Prints the unstable features every crate that would be copied enables, flagging those
+    /// that are stabilized or removed upstream.
+    Features(FeaturesOpt),
 }
 
-fn main() -> std::io::Result<()> {
-    env_logger::init();
+#[derive(Debug, StructOpt)]
+struct ConfigOpt {
+    /// Path to the configuration file [default: publisher.toml if it exists]
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Path to the upstream rust checkout, instead of `root` in the configuration.
+    #[structopt(long, parse(from_os_str))]
+    root: Option<PathBuf>,
+    /// Version of the copied crates, instead of the one generated from `version-template`.
+    #[structopt(long)]
+    crate_version: Option<Version>,
+}
 
-    let opt: Opt = Opt::from_args();
+/// Options selecting the crates to work on.
+#[derive(Debug, StructOpt)]
+struct CrateOpt {
+    #[structopt(flatten)]
+    config: ConfigOpt,
+    /// Comma-separated kinds of dependencies (`normal`, `build` or `dev`) that pull local crates
+    /// into the copied set, instead of `dependency-kinds` in the configuration.
+    #[structopt(
+        long,
+        use_delimiter = true,
+        parse(try_from_str = config::parse_dependency_kind)
+    )]
+    dependency_kinds: Vec<DependencyKind>,
+    /// Crates to copy, instead of `roots` in the configuration.
+    #[structopt(name = "CRATE")]
+    crates: Vec<String>,
+}
 
-    let mut command = MetadataCommand::new();
-    command.current_dir(&opt.root);
-    command.no_deps();
-    let metadata = command.exec().expect("cargo metadata failed");
+#[derive(Debug, StructOpt)]
+struct CopyOpt {
+    #[structopt(flatten)]
+    crates: CrateOpt,
+    /// Directory to copy the crates to, instead of `out` in the configuration.
+    #[structopt(short, long, parse(from_os_str))]
+    out: Option<PathBuf>,
+    #[structopt(short, long)]
+    force: bool,
+    /// Prefix added to the name of every copied crate, instead of `name-template`.
+    #[structopt(long)]
+    prefix: Option<String>,
+    /// Print what would be done instead of doing it.
+    #[structopt(long)]
+    dry_run: bool,
+    /// Print the plan of `--dry-run` as JSON.
+    #[structopt(long, requires = "dry-run")]
+    json: bool,
+}
 
-    let crates_to_copy = find_crates_to_copy(&metadata, opt.crates.into_iter());
-    debug!("Found {} crates", crates_to_copy.len());
+#[derive(Debug, StructOpt)]
+struct PublishOpt {
+    #[structopt(flatten)]
+    copy: CopyOpt,
+    #[structopt(flatten)]
+    registry: publish::Registry,
+}
 
-    if opt.force {
-        fs::remove_dir_all(&opt.out)?;
-    }
+#[derive(Debug, StructOpt)]
+struct GraphOpt {
+    #[structopt(flatten)]
+    crates: CrateOpt,
+    /// Output format, `dot` or `json`.
+    #[structopt(long, default_value = "dot")]
+    format: export::Format,
+}
 
-    let mut cargo_toml_content = "[workspace]\nmembers = [\n".to_owned();
-    for krate in crates_to_copy {
-        let to = opt.out.clone().join(krate.root_path.file_name().unwrap());
-        info!("copying {} from {:?} to {:?}", krate.name, krate.root_path, to);
+#[derive(Debug, StructOpt)]
+struct WhyOpt {
+    /// The crate to explain, e.g., `rustc_index`.
+    #[structopt(name = "TARGET")]
+    target: String,
+    #[structopt(flatten)]
+    crates: CrateOpt,
+}
 
-        copy_dir_all(&krate.root_path, &to)?;
+#[derive(Debug, StructOpt)]
+struct FeaturesOpt {
+    #[structopt(flatten)]
+    crates: CrateOpt,
+}
 
-        if CRATES_WHICH_REQUIRES_RUSTC_PRIVATE_FEATURES.contains(&krate.name) {
-            add_rustc_private_feature(&krate, &to)?;
-        }
+#[derive(Debug, StructOpt)]
+struct VersionOpt {
+    #[structopt(flatten)]
+    config: ConfigOpt,
+}
 
-        cargo_toml_content.push_str(&format!("  \"{}\",\n", krate.root_path.file_name().unwrap().to_string_lossy()));
+impl ConfigOpt {
+    /// Loads the configuration file and overrides it with the command line options.
+    fn load(&self) -> io::Result<Config> {
+        let mut config = Config::load(self.config.as_deref())?;
+        if let Some(root) = &self.root {
+            config.root = root.clone();
+        }
+        if let Some(version) = &self.crate_version {
+            config.version_template = version.to_string();
+        }
+        Ok(config)
     }
-    cargo_toml_content.push_str("]\n");
-
-    let cargo_toml_file_path = opt.out.join("Cargo.toml");
-    let mut f = fs::File::create(cargo_toml_file_path)?;
-    f.write_all(cargo_toml_content.as_bytes())?;
-
-    Ok(())
 }
 
-fn find_crates_to_copy(
-    metadata: &Metadata,
-    crates: impl Iterator<Item = String>,
-) -> BTreeSet<LocalCrate<'_>> {
-    crates
-        .flat_map(|krate| get_local_dependencies_of_crate(metadata, &krate))
-        .collect()
+impl CrateOpt {
+    fn load_config(&self) -> io::Result<Config> {
+        let mut config = self.config.load()?;
+        if !self.dependency_kinds.is_empty() {
+            config.dependency_kinds = self.dependency_kinds.clone();
+        }
+        if !self.crates.is_empty() {
+            config.roots = self.crates.clone();
+        }
+        if config.roots.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No crates to copy: pass them as arguments or set `roots` in the configuration",
+            ));
+        }
+        Ok(config)
+    }
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
-struct LocalCrate<'a> {
-    name: &'a str,
-    root_path: &'a Path,
-    lib_path: &'a Path,
+impl CopyOpt {
+    fn load_config(&self) -> io::Result<Config> {
+        let mut config = self.crates.load_config()?;
+        if let Some(out) = &self.out {
+            config.out = out.clone();
+        }
+        if let Some(prefix) = &self.prefix {
+            config.name_template = format!("{}{{name}}", prefix);
+        }
+        Ok(config)
+    }
 }
 
-fn get_local_dependencies_of_crate<'a>(
-    metadata: &'a Metadata,
-    krate: &str,
-) -> BTreeSet<LocalCrate<'a>> {
-    let package = metadata
-        .packages
-        .iter()
-        .find(|p| p.name == krate || format!("lib{}", p.name) == krate)
-        .expect(&format!("Could not find {}", krate));
-
-    let this_crate = LocalCrate {
-        name: package.name.as_str(),
-        root_path: package
-            .manifest_path
-            .parent()
-            .expect("Manifest path's parent directory does not exist"),
-        lib_path: package.targets[0].src_path.as_path(),
-    };
-
-    let local_dependencies = package
-        .dependencies
-        .iter()
-        .filter(|dep| dep.source.is_none())
-        .flat_map(|dep| get_local_dependencies_of_crate(metadata, &dep.name));
-
-    once(this_crate).chain(local_dependencies).collect()
-}
+fn main() -> std::io::Result<()> {
+    env_logger::init();
 
-fn copy_dir_all<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
-    for entry in WalkDir::new(from.as_ref()) {
-        let entry = entry?;
-        let relative_source_path = entry.path().strip_prefix(from.as_ref()).expect("Invalid path");
-        let target_path = to.as_ref().to_path_buf().join(relative_source_path);
-        if entry.file_type().is_dir() {
-            create_dir_all(target_path)?;
-        } else {
-            fs::copy(entry.path(), target_path)?;
+    match Opt::from_args() {
+        Opt::Copy(opt) => {
+            let config = opt.load_config()?;
+            let metadata = workspace::load_metadata(&config.root, &config.workspace_members)?;
+            let graph = CrateGraph::new(&metadata);
+            let crates = graph.closure(&config.roots, &config.dependency_kinds)?;
+            let pipeline = Pipeline::from_config(&config, &graph, &crates)?;
+            let plan = plan::plan(&config, &graph, &crates, &pipeline)?;
+            if opt.dry_run {
+                print_plan(&plan, opt.json)?;
+            } else {
+                copy(&plan, opt.force)?;
+            }
+        }
+        Opt::Publish(opt) => {
+            let config = opt.copy.load_config()?;
+            let metadata = workspace::load_metadata(&config.root, &config.workspace_members)?;
+            let graph = CrateGraph::new(&metadata);
+            let crates = graph.closure(&config.roots, &config.dependency_kinds)?;
+            let order = graph.leaf_first_order(&crates)?;
+            let pipeline = Pipeline::from_config(&config, &graph, &crates)?;
+            let plan = plan::plan(&config, &graph, &crates, &pipeline)?;
+            if opt.copy.dry_run {
+                print_plan(&plan, opt.copy.json)?;
+                if !opt.copy.json {
+                    let order: Vec<_> = order.iter().map(|k| plan.names[k.name].as_str()).collect();
+                    println!("\nPublish order: {}", order.join(", "));
+                }
+                plan.check_publishable()?;
+            } else {
+                plan.check_publishable()?;
+                copy(&plan, opt.copy.force)?;
+                publish::publish_all(&order, &config.out, &plan.names, &opt.registry)?;
+            }
+        }
+        Opt::Graph(opt) => {
+            let config = opt.crates.load_config()?;
+            let metadata = workspace::load_metadata(&config.root, &config.workspace_members)?;
+            let graph = CrateGraph::new(&metadata);
+            let crates = graph.closure(&config.roots, &config.dependency_kinds)?;
+            print!("{}", export::export(&graph, &crates, opt.format)?);
+        }
+        Opt::Why(opt) => {
+            let config = opt.crates.load_config()?;
+            let metadata = workspace::load_metadata(&config.root, &config.workspace_members)?;
+            let graph = CrateGraph::new(&metadata);
+            let paths = graph.paths_to(&config.roots, &opt.target, &config.dependency_kinds)?;
+            if paths.is_empty() {
+                println!(
+                    "{} is not pulled in by {}",
+                    opt.target,
+                    config.roots.join(", ")
+                );
+            }
+            for (root, edges) in paths {
+                print!("{}", root.name);
+                for edge in edges {
+                    match (edge.kind, &edge.target) {
+                        (DependencyKind::Normal, None) => print!(" -> {}", edge.to),
+                        (kind, None) => print!(" -> {} ({})", edge.to, graph::kind_name(kind)),
+                        (kind, Some(target)) => {
+                            print!(" -> {} ({}, {})", edge.to, graph::kind_name(kind), target)
+                        }
+                    }
+                }
+                println!();
+            }
+        }
+        Opt::Features(opt) => {
+            let config = opt.crates.load_config()?;
+            let metadata = workspace::load_metadata(&config.root, &config.workspace_members)?;
+            let graph = CrateGraph::new(&metadata);
+            let crates = graph.closure(&config.roots, &config.dependency_kinds)?;
+            let tables = FeatureTables::load(&config.root)?;
+            print!("{}", features::report(&crates, &tables)?);
+        }
+        Opt::Version(opt) => {
+            let config = opt.config.load()?;
+            let version = version::upstream_version(&config.root, &config.version_template)?;
+            println!("{}", version);
         }
     }
 
     Ok(())
 }
 
-fn add_rustc_private_feature(source_krate: &LocalCrate<'_>, to: &Path) -> io::Result<()> {
-    let to_path = to.join(source_krate.lib_path.file_name().unwrap());
-    debug!("Modifying {:?} using {:?}", to_path, source_krate.lib_path);
-    let source_content = fs::read_to_string(source_krate.lib_path)?;
-
-    let mut f = fs::File::create(to_path)?;
-    let target_content = "#![feature(rustc_private)]\n".to_owned() + &source_content;
-    f.write_all(target_content.as_bytes())
+fn copy(plan: &plan::Plan<'_>, force: bool) -> io::Result<()> {
+    plan.execute(force)?;
+    for (old_name, new_name) in &plan.names {
+        println!("{} -> {}", old_name, new_name);
+    }
+    Ok(())
 }
 
+fn print_plan(plan: &plan::Plan<'_>, json: bool) -> io::Result<()> {
+    if json {
+        let json = serde_json::to_string_pretty(&plan.to_json()).map_err(io::Error::other)?;
+        println!("{}", json);
+    } else {
+        print!("{}", plan);
+    }
+    Ok(())
+}
@@ -0,0 +1,296 @@
+//! Reading and rewriting `Cargo.toml` files.
+//!
+//! Edits preserve the formatting, ordering and comments of everything they do not touch, so that
+//! the copied manifests stay close to the upstream ones.
+
+use std::{fs, io, path::Path};
+
+use cargo_metadata::DependencyKind;
+use toml_edit::{value, Array, DocumentMut, InlineTable, Item, Table, TableLike, Value};
+
+const DEPENDENCY_TABLES: &[(&str, DependencyKind)] = &[
+    ("dependencies", DependencyKind::Normal),
+    ("dev-dependencies", DependencyKind::Development),
+    ("build-dependencies", DependencyKind::Build),
+];
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    document: DocumentMut,
+}
+
+impl Manifest {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Manifest> {
+        let path = path.as_ref();
+        Manifest::parse(path, &fs::read_to_string(path)?)
+    }
+
+    /// Parses `content`, which was read from `path`, e.g., before being patched.
+    pub fn parse(path: &Path, content: &str) -> io::Result<Manifest> {
+        let document = content.parse::<DocumentMut>().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse {:?}: {}", path, e),
+            )
+        })?;
+        Ok(Manifest { document })
+    }
+
+    pub fn render(&self) -> String {
+        self.document.to_string()
+    }
+
+    pub fn set_package_name(&mut self, name: &str) {
+        self.set_package_field("name", name);
+    }
+
+    pub fn set_package_version(&mut self, version: &str) {
+        self.set_package_field("version", version);
+    }
+
+    pub fn set_package_build(&mut self, path: &str) {
+        self.set_package_field("build", path);
+    }
+
+    pub fn package_field(&self, key: &str) -> Option<&Item> {
+        self.get(&["package", key])
+    }
+
+    pub fn set_package_field(&mut self, key: &str, field: &str) {
+        self.set(&["package", key], value(field));
+    }
+
+    pub fn remove_package_field(&mut self, key: &str) -> Option<Item> {
+        self.remove(&["package", key])
+    }
+
+    /// Returns the item at `path`, e.g., `["workspace", "package", "version"]`.
+    pub fn get(&self, path: &[&str]) -> Option<&Item> {
+        path.iter()
+            .try_fold(self.document.as_item(), |item, key| item.get(key))
+    }
+
+    pub fn get_mut(&mut self, path: &[&str]) -> Option<&mut Item> {
+        path.iter()
+            .try_fold(self.document.as_item_mut(), |item, key| item.get_mut(key))
+    }
+
+    /// Sets the item at `path`, creating the tables leading to it as needed.
+    pub fn set(&mut self, path: &[&str], item: Item) {
+        let mut current = self.document.as_item_mut();
+        for (i, key) in path.iter().enumerate() {
+            current = &mut current[key];
+            // Only print the headers of the tables that end up with fields of their own.
+            if current.is_none() && i + 1 < path.len() {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                *current = Item::Table(table);
+            }
+        }
+        *current = item;
+    }
+
+    pub fn remove(&mut self, path: &[&str]) -> Option<Item> {
+        let (key, parent) = path.split_last()?;
+        self.get_mut(parent)?.as_table_like_mut()?.remove(key)
+    }
+
+    /// Pins the name of the library target, so that renaming the package does not change the
+    /// name of the crate seen by rustc.
+    pub fn set_lib_name_if_absent(&mut self, name: &str) {
+        if self.get(&["lib", "name"]).is_none() {
+            self.set(&["lib", "name"], value(name));
+        }
+    }
+
+    /// Replaces the members of the `[workspace]` of a workspace root manifest.
+    pub fn set_workspace_members(&mut self, members: &[String]) -> io::Result<()> {
+        let workspace = self
+            .document
+            .get_mut("workspace")
+            .and_then(Item::as_table_like_mut)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The manifest has no [workspace]",
+                )
+            })?;
+        let mut members: Array = members.iter().map(String::as_str).collect();
+        for member in members.iter_mut() {
+            member.decor_mut().set_prefix("\n  ");
+        }
+        members.set_trailing("\n");
+        members.set_trailing_comma(true);
+        workspace.insert("members", value(members));
+        Ok(())
+    }
+
+    /// Returns every dependency in the manifest as `(kind, name in Cargo.toml, specification)`,
+    /// including the target-specific ones.
+    pub fn dependencies_mut(&mut self) -> Vec<(DependencyKind, String, &mut Item)> {
+        self.dependency_tables_mut()
+            .into_iter()
+            .flat_map(|(kind, table)| {
+                table
+                    .iter_mut()
+                    .map(move |(key, spec)| (kind, key.get().to_owned(), spec))
+            })
+            .collect()
+    }
+
+    /// Removes every dependency for which `f` returns `false`, and returns the names of the
+    /// removed ones in `Cargo.toml`.
+    pub fn retain_dependencies<F>(&mut self, mut f: F) -> Vec<String>
+    where
+        F: FnMut(DependencyKind, &str, &Item) -> bool,
+    {
+        let mut removed = vec![];
+        for (kind, table) in self.dependency_tables_mut() {
+            let keys: Vec<String> = table
+                .iter()
+                .filter(|(key, spec)| !f(kind, key, spec))
+                .map(|(key, _)| key.to_owned())
+                .collect();
+            for key in keys {
+                table.remove(&key);
+                removed.push(key);
+            }
+        }
+        removed
+    }
+
+    fn dependency_tables_mut(&mut self) -> Vec<(DependencyKind, &mut dyn TableLike)> {
+        let mut tables = vec![];
+        for (key, item) in self.document.iter_mut() {
+            let table = match item.as_table_like_mut() {
+                Some(table) => table,
+                None => continue,
+            };
+            if key.get() == "target" {
+                for (_, target) in table.iter_mut() {
+                    if let Some(target) = target.as_table_like_mut() {
+                        tables.extend(dependency_tables(target));
+                    }
+                }
+            } else if let Some(kind) = dependency_kind(key.get()) {
+                tables.push((kind, table));
+            }
+        }
+        tables
+    }
+}
+
+fn dependency_kind(key: &str) -> Option<DependencyKind> {
+    DEPENDENCY_TABLES
+        .iter()
+        .find(|(table, _)| *table == key)
+        .map(|(_, kind)| *kind)
+}
+
+fn dependency_tables(
+    table: &mut dyn TableLike,
+) -> impl Iterator<Item = (DependencyKind, &mut dyn TableLike)> {
+    table.iter_mut().filter_map(|(key, item)| {
+        let kind = dependency_kind(key.get())?;
+        Some((kind, item.as_table_like_mut()?))
+    })
+}
+
+/// Returns the name of the package a dependency specification refers to.
+pub fn dependency_package_name<'a>(key: &'a str, spec: &'a Item) -> &'a str {
+    spec.get("package").and_then(Item::as_str).unwrap_or(key)
+}
+
+/// Makes a dependency refer to the package `package` while keeping its name in `Cargo.toml`.
+pub fn set_dependency_package(spec: &mut Item, package: &str) {
+    if let Some(version) = spec.as_str() {
+        let mut table = InlineTable::new();
+        table.insert("version", Value::from(version));
+        *spec = value(table);
+    }
+    insert_into_dependency(spec, "package", package);
+}
+
+/// Sets the version requirement of a dependency specified by a table, e.g., a path dependency.
+pub fn set_dependency_version(spec: &mut Item, version: &str) {
+    insert_into_dependency(spec, "version", version);
+}
+
+fn insert_into_dependency(spec: &mut Item, key: &str, field: &str) {
+    if let Some(table) = spec.as_inline_table_mut() {
+        table.insert(key, Value::from(field));
+        // Otherwise the new field keeps the space before the closing brace of the last one.
+        table.fmt();
+    } else if let Some(table) = spec.as_table_like_mut() {
+        table.insert(key, value(field));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(content: &str) -> Manifest {
+        Manifest {
+            document: content.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn adds_a_lib_table() {
+        let mut m = manifest("[package]\nname = \"b\"\n\n[dependencies]\nc = \"1\"\n");
+        m.set_lib_name_if_absent("b");
+        assert_eq!(
+            m.render(),
+            "[package]\nname = \"b\"\n\n[dependencies]\nc = \"1\"\n\n[lib]\nname = \"b\"\n"
+        );
+
+        let mut m = manifest("[lib]\npath = \"lib.rs\"\n");
+        m.set_lib_name_if_absent("b");
+        assert_eq!(m.render(), "[lib]\npath = \"lib.rs\"\nname = \"b\"\n");
+
+        let mut m = manifest("[lib]\nname = \"a\"\n");
+        m.set_lib_name_if_absent("b");
+        assert_eq!(m.render(), "[lib]\nname = \"a\"\n");
+    }
+
+    #[test]
+    fn rewrites_dependencies_in_place() {
+        let mut m = manifest(
+            "[dependencies]\n# Comment.\na = \"1\"\nb = { path = \"../b\" }\n\n\
+             [target.'cfg(unix)'.dev-dependencies]\nc = { path = \"../c\" }\n",
+        );
+        for (_, key, spec) in m.dependencies_mut() {
+            match key.as_str() {
+                "a" => set_dependency_package(spec, "new-a"),
+                _ => set_dependency_version(spec, "=1.0.0"),
+            }
+        }
+        let removed =
+            m.retain_dependencies(|kind, key, _| kind != DependencyKind::Development || key != "c");
+        assert_eq!(removed, ["c"]);
+        assert_eq!(
+            m.render(),
+            "[dependencies]\n# Comment.\na = { version = \"1\", package = \"new-a\" }\n\
+             b = { path = \"../b\", version = \"=1.0.0\" }\n\n\
+             [target.'cfg(unix)'.dev-dependencies]\n"
+        );
+    }
+
+    #[test]
+    fn sets_nested_items() {
+        let mut m = manifest("[package]\nname = \"a\"\n");
+        m.set(&["profile", "release", "debug"], value(true));
+        assert_eq!(
+            m.render(),
+            "[package]\nname = \"a\"\n\n[profile.release]\ndebug = true\n"
+        );
+        assert_eq!(
+            m.remove(&["profile", "release", "debug"])
+                .unwrap()
+                .as_bool(),
+            Some(true)
+        );
+        assert!(m.get(&["profile", "release", "debug"]).is_none());
+    }
+}
@@ -0,0 +1,308 @@
+//! Removing the parts of upstream manifests that only make sense inside the rustc workspace, so
+//! that every copied crate builds both in the generated workspace and on its own.
+
+use std::{
+    io,
+    path::{Component, Path},
+};
+
+use toml_edit::{value, InlineTable, Item, Value};
+
+use crate::{graph::LocalCrate, manifest::Manifest};
+
+/// Kinds of targets other than the library, which rely on files and crates that are not copied,
+/// along with the key turning off their discovery.
+const TARGET_KINDS: &[(&str, &str)] = &[
+    ("bin", "autobins"),
+    ("test", "autotests"),
+    ("bench", "autobenches"),
+    ("example", "autoexamples"),
+];
+
+/// `[package]` fields holding a path relative to the manifest.
+const PATH_FIELDS: &[&str] = &["readme", "license-file"];
+
+/// Rewrites the manifest of `krate` so that it does not depend on the upstream workspace, whose
+/// root manifest is `upstream` at `upstream_root`:
+///
+/// - `workspace = "..."` and `[workspace]` are removed.
+/// - Fields and dependencies inherited with `workspace = true` are resolved.
+/// - `doctest = false` and `test = false` in `[lib]`, which are tuned for x.py, are removed.
+/// - Binaries, tests, benchmarks and examples are dropped.
+///
+/// Returns a description of every change made.
+pub fn normalize(
+    krate: &LocalCrate<'_>,
+    manifest: &mut Manifest,
+    upstream: &Manifest,
+    upstream_root: &Path,
+) -> io::Result<Vec<String>> {
+    let mut changes = vec![];
+
+    if let Some(workspace) = manifest.remove_package_field("workspace") {
+        changes.push(format!(
+            "removed `workspace = {}`",
+            workspace.to_string().trim()
+        ));
+    }
+    if manifest.remove(&["workspace"]).is_some() {
+        changes.push("removed [workspace]".to_owned());
+    }
+
+    let inherited_fields: Vec<String> = manifest
+        .get(&["package"])
+        .and_then(Item::as_table_like)
+        .map_or(vec![], |package| {
+            package
+                .iter()
+                .filter(|(_, field)| is_inherited(field))
+                .map(|(key, _)| key.to_owned())
+                .collect()
+        });
+    for key in inherited_fields {
+        let mut field = upstream
+            .get(&["workspace", "package", &key])
+            .cloned()
+            .ok_or_else(|| not_in_workspace(krate, &format!("package.{}", key)))?;
+        if PATH_FIELDS.contains(&key.as_str()) {
+            if let Some(path) = field.as_str() {
+                let path = rebase(krate, upstream_root, path);
+                // Only the crate directory is copied.
+                if path.starts_with("..") {
+                    manifest.remove_package_field(&key);
+                    changes.push(format!(
+                        "removed the {} inherited from the upstream workspace, {:?} is not copied",
+                        key, path
+                    ));
+                    continue;
+                }
+                field = value(path);
+            }
+        }
+        manifest.set(&["package", &key], field);
+        changes.push(format!("inherited the {} from the upstream workspace", key));
+    }
+
+    for (_, key, spec) in manifest.dependencies_mut() {
+        if !is_inherited(spec) {
+            continue;
+        }
+        let base = upstream
+            .get(&["workspace", "dependencies", &key])
+            .ok_or_else(|| not_in_workspace(krate, &format!("dependencies.{}", key)))?;
+        *spec = value(inherit_dependency(krate, upstream_root, base, spec));
+        changes.push(format!(
+            "inherited dependency {} from the upstream workspace",
+            key
+        ));
+    }
+
+    if manifest.get(&["lints"]).is_some_and(is_inherited) {
+        match upstream.get(&["workspace", "lints"]).cloned() {
+            Some(lints) => {
+                manifest.set(&["lints"], lints);
+                changes.push("inherited the lints from the upstream workspace".to_owned());
+            }
+            None => {
+                manifest.remove(&["lints"]);
+                changes
+                    .push("removed [lints], which the upstream workspace does not set".to_owned());
+            }
+        }
+    }
+
+    for key in &["doctest", "test"] {
+        if manifest.get(&["lib", key]).and_then(Item::as_bool) == Some(false) {
+            manifest.remove(&["lib", key]);
+            changes.push(format!("removed `{} = false` from [lib]", key));
+        }
+    }
+
+    for (kind, auto) in TARGET_KINDS {
+        if manifest.remove(&[kind]).is_some() {
+            changes.push(format!("removed the [[{}]] targets", kind));
+        }
+        // Targets found in the usual places, e.g., `src/main.rs`, are still copied.
+        if krate
+            .targets
+            .iter()
+            .any(|target| target.kind.iter().any(|k| k == kind))
+        {
+            manifest.set(&["package", auto], value(false));
+            changes.push(format!("set `{} = false`", auto));
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Whether `item` is `{ workspace = true, ... }`.
+fn is_inherited(item: &Item) -> bool {
+    item.get("workspace").and_then(Item::as_bool) == Some(true)
+}
+
+/// Merges the specification of a dependency in `[workspace.dependencies]`, `base`, with that of
+/// a member inheriting it, `spec`, which can only add features and make it optional.
+fn inherit_dependency(
+    krate: &LocalCrate<'_>,
+    upstream_root: &Path,
+    base: &Item,
+    spec: &Item,
+) -> InlineTable {
+    let mut table = InlineTable::new();
+    if let Some(version) = base.as_str() {
+        table.insert("version", Value::from(version));
+    } else if let Some(base) = base.as_table_like() {
+        for (key, field) in base.iter() {
+            if let Some(field) = field.as_value() {
+                table.insert(key, field.clone());
+            }
+        }
+    }
+    if let Some(path) = table.get("path").and_then(Value::as_str) {
+        let path = rebase(krate, upstream_root, path);
+        table.insert("path", Value::from(path));
+    }
+
+    if let Some(spec) = spec.as_table_like() {
+        for (key, field) in spec.iter() {
+            let field = match field.as_value() {
+                Some(field) if key != "workspace" => field,
+                _ => continue,
+            };
+            match (
+                table.get_mut(key).and_then(Value::as_array_mut),
+                field.as_array(),
+            ) {
+                (Some(features), Some(added)) if key == "features" => {
+                    features.extend(added.iter().cloned());
+                    features.fmt();
+                }
+                _ => {
+                    table.insert(key, field.clone());
+                }
+            }
+        }
+    }
+    table.fmt();
+    table
+}
+
+/// Turns `path`, relative to the upstream workspace root, into a path relative to the root of
+/// `krate`.
+fn rebase(krate: &LocalCrate<'_>, upstream_root: &Path, path: &str) -> String {
+    let target = upstream_root.join(path);
+    let from: Vec<_> = krate.root_path.components().collect();
+    let to: Vec<_> = target
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let components: Vec<_> = (common..from.len())
+        .map(|_| "..".into())
+        .chain(
+            to[common..]
+                .iter()
+                .map(|component| component.as_os_str().to_string_lossy()),
+        )
+        .collect();
+    if components.is_empty() {
+        ".".to_owned()
+    } else {
+        components.join("/")
+    }
+}
+
+fn not_in_workspace(krate: &LocalCrate<'_>, key: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "{} inherits `{}` from the upstream workspace, which does not set it",
+            krate.name, key
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::CrateGraph, testing::Workspace};
+
+    #[test]
+    fn rebases_paths() {
+        let krate = LocalCrate {
+            name: "a",
+            root_path: Path::new("/rust/compiler/a"),
+            targets: &[],
+        };
+        let rebase = |path| rebase(&krate, Path::new("/rust"), path);
+        assert_eq!(rebase("compiler/b"), "../b");
+        assert_eq!(rebase("./compiler/a/README.md"), "README.md");
+        assert_eq!(rebase("compiler/a"), ".");
+        assert_eq!(rebase("LICENSE"), "../../LICENSE");
+    }
+
+    #[test]
+    fn normalizes_manifests() {
+        let workspace = Workspace::new(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"compiler/a\", \"compiler/b\"]\n\n\
+                 [workspace.package]\nedition = \"2021\"\nlicense-file = \"LICENSE\"\n\
+                 readme = \"compiler/a/README.md\"\n\n\
+                 [workspace.dependencies]\nb = { path = \"compiler/b\", features = [\"x\"] }\n\n\
+                 [workspace.lints.rust]\nunsafe_code = \"deny\"\n",
+            ),
+            ("LICENSE", ""),
+            (
+                "compiler/a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\nedition.workspace = true\n\
+                 license-file.workspace = true\nreadme.workspace = true\nworkspace = \"../..\"\n\n\
+                 [lib]\ndoctest = false\n\n\
+                 [[bench]]\nname = \"speed\"\nharness = false\n\n\
+                 [dependencies]\nb = { workspace = true, features = [\"y\"], optional = true }\n\n\
+                 [lints]\nworkspace = true\n",
+            ),
+            ("compiler/a/README.md", ""),
+            ("compiler/a/src/lib.rs", ""),
+            ("compiler/a/benches/speed.rs", "fn main() {}\n"),
+            (
+                "compiler/b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.0.0\"\n\n[features]\nx = []\ny = []\n",
+            ),
+            ("compiler/b/src/lib.rs", ""),
+        ]);
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let krate = graph.find("a").unwrap();
+        let root = workspace.root().canonicalize().unwrap();
+        let upstream = Manifest::open(root.join("Cargo.toml")).unwrap();
+        let mut manifest = Manifest::open(krate.root_path.join("Cargo.toml")).unwrap();
+
+        let changes = normalize(&krate, &mut manifest, &upstream, &root).unwrap();
+        assert_eq!(
+            manifest.render(),
+            "[package]\nname = \"a\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\
+             readme = \"README.md\"\nautobenches = false\n\n\
+             [lib]\n\n\
+             [dependencies]\n\
+             b = { path = \"../b\", features = [\"x\", \"y\"], optional = true }\n\n\
+             [lints.rust]\nunsafe_code = \"deny\"\n"
+        );
+        assert_eq!(
+            changes,
+            [
+                "removed `workspace = \"../..\"`",
+                "inherited the edition from the upstream workspace",
+                "removed the license-file inherited from the upstream workspace, \
+                 \"../../LICENSE\" is not copied",
+                "inherited the readme from the upstream workspace",
+                "inherited dependency b from the upstream workspace",
+                "inherited the lints from the upstream workspace",
+                "removed `doctest = false` from [lib]",
+                "removed the [[bench]] targets",
+                "set `autobenches = false`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Filling in the `[package]` fields crates.io needs, which upstream crates do not set.
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{graph::LocalCrate, manifest::Manifest, transform::SourceEdit};
+
+/// `[package]` fields of the copied crates. `{name}` is replaced with the upstream name.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PackageMetadata {
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub repository: Option<String>,
+    pub homepage: Option<String>,
+    pub documentation: Option<String>,
+    /// Path to a README copied into every crate, relative to the current directory.
+    pub readme: Option<PathBuf>,
+}
+
+/// Fills in the fields of `defaults` that the manifest of `krate` lacks, sets those of
+/// `overrides`, the per-crate settings, unconditionally, and removes `publish = false`.
+///
+/// Returns the changes made, along with the README to copy into the crate, if any. `files` are
+/// the files copied from upstream, which the README must not overwrite.
+pub fn inject(
+    krate: &LocalCrate<'_>,
+    manifest: &mut Manifest,
+    defaults: &PackageMetadata,
+    overrides: Option<&PackageMetadata>,
+    files: &BTreeSet<PathBuf>,
+) -> io::Result<(Vec<String>, Option<SourceEdit>)> {
+    let mut changes = vec![];
+    let no_overrides = PackageMetadata::default();
+    let overrides = overrides.unwrap_or(&no_overrides);
+    let fields = [
+        ("description", &defaults.description, &overrides.description),
+        ("license", &defaults.license, &overrides.license),
+        ("repository", &defaults.repository, &overrides.repository),
+        ("homepage", &defaults.homepage, &overrides.homepage),
+        (
+            "documentation",
+            &defaults.documentation,
+            &overrides.documentation,
+        ),
+    ];
+    for (key, default, value) in &fields {
+        let value = match (value, default) {
+            (Some(value), _) => value,
+            (None, Some(default)) => {
+                if let Some(upstream) = upstream_field(manifest, key) {
+                    changes.push(format!("kept the upstream {}", upstream));
+                    continue;
+                }
+                default
+            }
+            (None, None) => continue,
+        };
+        let value = value.replace("{name}", krate.name);
+        // Only one of them is needed.
+        if *key == "license" && manifest.remove_package_field("license-file").is_some() {
+            changes.push("removed the upstream license-file".to_owned());
+        }
+        manifest.set_package_field(key, &value);
+        changes.push(format!("set the {} to {:?}", key, value));
+    }
+
+    if manifest
+        .package_field("publish")
+        .and_then(toml_edit::Item::as_bool)
+        == Some(false)
+    {
+        manifest.remove_package_field("publish");
+        changes.push("removed `publish = false`".to_owned());
+    }
+
+    let readme_path = match (&overrides.readme, &defaults.readme) {
+        (Some(path), _) => Some(path),
+        (None, Some(path)) => match upstream_field(manifest, "readme") {
+            Some(upstream) => {
+                changes.push(format!("kept the upstream {}", upstream));
+                None
+            }
+            None => Some(path),
+        },
+        (None, None) => None,
+    };
+    let readme = match readme_path {
+        Some(path) => Some(readme(krate, path, files)?),
+        None => None,
+    };
+    if let Some(readme) = &readme {
+        let path = readme.path.to_string_lossy();
+        manifest.set_package_field("readme", &path);
+        changes.push(format!("set the readme to {:?}", path));
+    }
+    Ok((changes, readme))
+}
+
+/// Describes the field of the manifest standing for `key`, if it is set, e.g., `license-file`
+/// for `license`.
+fn upstream_field(manifest: &Manifest, key: &str) -> Option<String> {
+    let keys: &[&str] = if key == "license" {
+        &["license", "license-file"]
+    } else {
+        &[key]
+    };
+    keys.iter().find_map(|key| {
+        let field = manifest.package_field(key)?;
+        Some(format!("{} {}", key, field.to_string().trim()))
+    })
+}
+
+fn readme(
+    krate: &LocalCrate<'_>,
+    path: &Path,
+    files: &BTreeSet<PathBuf>,
+) -> io::Result<SourceEdit> {
+    let file_name = PathBuf::from(path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid readme path {:?}", path),
+        )
+    })?);
+    if files.contains(&file_name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: copying the readme {:?} would overwrite {:?} from upstream",
+                krate.name, path, file_name
+            ),
+        ));
+    }
+    Ok(SourceEdit {
+        content: fs::read_to_string(path)?,
+        changes: vec![format!("copied from {}", path.display())],
+        path: file_name,
+    })
+}
+
+/// Returns the fields crates.io requires that `manifest` lacks, along with `publish` if it
+/// forbids publishing.
+pub fn missing_fields(manifest: &Manifest) -> Vec<&'static str> {
+    let mut missing = vec![];
+    if manifest.package_field("description").is_none() {
+        missing.push("description");
+    }
+    // A license file is as good as a license.
+    if manifest.package_field("license").is_none()
+        && manifest.package_field("license-file").is_none()
+    {
+        missing.push("license");
+    }
+    if manifest
+        .package_field("publish")
+        .and_then(toml_edit::Item::as_bool)
+        == Some(false)
+    {
+        missing.push("publish");
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    fn parse(content: &str) -> Manifest {
+        Manifest::parse(Path::new("Cargo.toml"), content).unwrap()
+    }
+
+    fn krate() -> LocalCrate<'static> {
+        LocalCrate {
+            name: "rustc_lexer",
+            root_path: Path::new("."),
+            targets: &[],
+        }
+    }
+
+    #[test]
+    fn fills_in_missing_fields() {
+        let mut manifest = parse(
+            "[package]\nname = \"rustc_lexer\"\nversion = \"0.0.0\"\n\
+             license-file = \"LICENSE\"\ndescription = \"Upstream\"\npublish = false\n",
+        );
+        let defaults = PackageMetadata {
+            description: Some("Copy of {name}".to_owned()),
+            license: Some("MIT OR Apache-2.0".to_owned()),
+            repository: Some("https://example.com/{name}".to_owned()),
+            ..PackageMetadata::default()
+        };
+        let overrides = PackageMetadata {
+            license: Some("MIT".to_owned()),
+            ..PackageMetadata::default()
+        };
+
+        let (changes, readme) = inject(
+            &krate(),
+            &mut manifest,
+            &defaults,
+            Some(&overrides),
+            &BTreeSet::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            changes,
+            [
+                "kept the upstream description \"Upstream\"",
+                "removed the upstream license-file",
+                "set the license to \"MIT\"",
+                "set the repository to \"https://example.com/rustc_lexer\"",
+                "removed `publish = false`",
+            ]
+        );
+        assert!(readme.is_none());
+        assert_eq!(
+            manifest.render(),
+            "[package]\nname = \"rustc_lexer\"\nversion = \"0.0.0\"\n\
+             description = \"Upstream\"\nlicense = \"MIT\"\n\
+             repository = \"https://example.com/rustc_lexer\"\n"
+        );
+        assert!(missing_fields(&manifest).is_empty());
+    }
+
+    #[test]
+    fn keeps_an_upstream_license_file() {
+        let mut manifest = parse("[package]\nname = \"rustc_lexer\"\nlicense-file = \"LICENSE\"\n");
+        let defaults = PackageMetadata {
+            license: Some("MIT".to_owned()),
+            ..PackageMetadata::default()
+        };
+
+        let (changes, _) =
+            inject(&krate(), &mut manifest, &defaults, None, &BTreeSet::new()).unwrap();
+        assert_eq!(changes, ["kept the upstream license-file \"LICENSE\""]);
+        assert_eq!(missing_fields(&manifest), ["description"]);
+    }
+
+    #[test]
+    fn copies_the_readme() {
+        let workspace = Workspace::new(&[("README.md", "# Copy\n")]);
+        let readme_path = workspace.root().join("README.md");
+        let defaults = PackageMetadata {
+            readme: Some(readme_path.clone()),
+            ..PackageMetadata::default()
+        };
+
+        let mut manifest = parse("[package]\nname = \"rustc_lexer\"\n");
+        let (changes, readme) =
+            inject(&krate(), &mut manifest, &defaults, None, &BTreeSet::new()).unwrap();
+        let readme = readme.unwrap();
+        assert_eq!(changes, ["set the readme to \"README.md\""]);
+        assert_eq!(readme.path, Path::new("README.md"));
+        assert_eq!(readme.content, "# Copy\n");
+
+        let upstream = [PathBuf::from("README.md")].iter().cloned().collect();
+        let mut manifest = parse("[package]\nname = \"rustc_lexer\"\n");
+        assert!(inject(&krate(), &mut manifest, &defaults, None, &upstream).is_err());
+
+        let mut manifest = parse("[package]\nname = \"rustc_lexer\"\nreadme = \"README\"\n");
+        let (changes, readme) =
+            inject(&krate(), &mut manifest, &defaults, None, &upstream).unwrap();
+        assert_eq!(changes, ["kept the upstream readme \"README\""]);
+        assert!(readme.is_none());
+        assert_eq!(
+            missing_fields(&parse("[package]\npublish = false\n")),
+            ["description", "license", "publish"]
+        );
+    }
+}
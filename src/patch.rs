@@ -0,0 +1,469 @@
+//! Applying the patch series of a copied crate, `patches/<crate>/*.patch`, in file name order.
+//!
+//! Patches are unified diffs, e.g., from `git format-patch`, with paths relative to the crate
+//! root. Each hunk is applied where its context matches, preferring the line it names, so that
+//! upstream changes elsewhere in a file do not break it.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+/// What to do with a patch that does not apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnConflict {
+    /// Stop the run.
+    Fail,
+    /// Leave the patch out entirely, and carry on with the next one.
+    Skip,
+}
+
+#[derive(Debug)]
+pub struct Patch {
+    /// Name of the patch file, e.g., `0001-fix-build.patch`.
+    pub name: String,
+    pub files: Vec<FilePatch>,
+}
+
+#[derive(Debug)]
+pub struct FilePatch {
+    /// Path of the patched file relative to the crate root.
+    pub path: PathBuf,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug)]
+struct Hunk {
+    /// The 1-based line the hunk starts at in the original file.
+    old_start: usize,
+    old: Vec<Line>,
+    new: Vec<Line>,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    text: String,
+    /// Whether the line is followed by `\ No newline at end of file`.
+    no_newline: bool,
+}
+
+/// What became of a hunk.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HunkStatus {
+    /// Applied at the given 1-based line.
+    Applied(usize),
+    /// The file already contains the result of the hunk.
+    AlreadyApplied,
+    Conflict,
+}
+
+/// What became of the hunks of a patch, as `(path, hunk number, status)`.
+#[derive(Debug)]
+pub struct PatchReport {
+    pub name: String,
+    pub hunks: Vec<(PathBuf, usize, HunkStatus)>,
+}
+
+impl PatchReport {
+    pub fn has_conflicts(&self) -> bool {
+        self.hunks
+            .iter()
+            .any(|(_, _, status)| *status == HunkStatus::Conflict)
+    }
+
+    /// Whether every hunk is already upstream, so that the patch can be dropped.
+    pub fn is_empty(&self) -> bool {
+        self.hunks
+            .iter()
+            .all(|(_, _, status)| *status == HunkStatus::AlreadyApplied)
+    }
+}
+
+impl fmt::Display for PatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hunks: Vec<_> = self
+            .hunks
+            .iter()
+            .map(|(path, number, status)| {
+                let status = match status {
+                    HunkStatus::Applied(line) => format!("applied at line {}", line),
+                    HunkStatus::AlreadyApplied => "already applied".to_owned(),
+                    HunkStatus::Conflict => "conflicts".to_owned(),
+                };
+                format!("{} hunk #{} {}", path.display(), number, status)
+            })
+            .collect();
+        write!(f, "{}: {}", self.name, hunks.join(", "))
+    }
+}
+
+/// Reads the patch series of the crate named `krate` in `dir`, in file name order.
+pub fn series(dir: &Path, krate: &str) -> io::Result<Vec<Patch>> {
+    let dir = dir.join(krate);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut paths = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "patch")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            Patch::parse(&name, &fs::read_to_string(path)?).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Failed to parse {:?}: {}", path, e),
+                )
+            })
+        })
+        .collect()
+}
+
+impl Patch {
+    /// Parses a unified diff, skipping everything that is not part of a file diff, e.g., the
+    /// mail headers of `git format-patch`.
+    pub fn parse(name: &str, text: &str) -> Result<Patch, String> {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut files: Vec<FilePatch> = vec![];
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            let is_file_header = lines.get(i + 1).is_some_and(|l| l.starts_with("+++ "));
+            if line.starts_with("--- ") && is_file_header {
+                let old_path = diff_path(&line[4..]);
+                let new_path = diff_path(&lines[i + 1][4..]);
+                if old_path == "/dev/null" || new_path == "/dev/null" {
+                    return Err(format!(
+                        "line {}: creating or deleting files is not supported",
+                        i + 1
+                    ));
+                }
+                files.push(FilePatch {
+                    path: PathBuf::from(strip_prefix(new_path)),
+                    hunks: vec![],
+                });
+                i += 2;
+            } else if line.starts_with("@@ ") {
+                let file = files
+                    .last_mut()
+                    .ok_or_else(|| format!("line {}: hunk without a file header", i + 1))?;
+                let (hunk, next) = parse_hunk(&lines, i)?;
+                file.hunks.push(hunk);
+                i = next;
+            } else {
+                i += 1;
+            }
+        }
+        Ok(Patch {
+            name: name.to_owned(),
+            files,
+        })
+    }
+
+    /// Applies the patch to `contents`, which maps the paths relative to the crate root to the
+    /// contents of the files, and reports what became of every hunk.
+    ///
+    /// `contents` is only changed if no hunk conflicts.
+    pub fn apply(&self, contents: &mut BTreeMap<PathBuf, String>) -> io::Result<PatchReport> {
+        let mut report = PatchReport {
+            name: self.name.clone(),
+            hunks: vec![],
+        };
+        let mut patched = BTreeMap::new();
+        for file in &self.files {
+            let content = match patched.get(&file.path).or_else(|| contents.get(&file.path)) {
+                Some(content) => content,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}: {:?} is not a file copied from upstream",
+                            self.name, file.path
+                        ),
+                    ))
+                }
+            };
+            let mut lines: Vec<Line> = content
+                .split_inclusive('\n')
+                .map(|line| Line {
+                    text: line.trim_end_matches('\n').to_owned(),
+                    no_newline: !line.ends_with('\n'),
+                })
+                .collect();
+
+            let mut offset: isize = 0;
+            for (i, hunk) in file.hunks.iter().enumerate() {
+                let start = hunk.old_start.saturating_sub(1) as isize;
+                let expected = (start + offset).max(0) as usize;
+                let status = match find(&lines, &hunk.old, expected) {
+                    // A hunk that only adds lines still finds its context once applied, so it
+                    // is already applied if its longer result is there too.
+                    Some(at)
+                        if hunk.new.len() > hunk.old.len() && matches_at(&lines, &hunk.new, at) =>
+                    {
+                        HunkStatus::AlreadyApplied
+                    }
+                    Some(at) => {
+                        lines.splice(at..at + hunk.old.len(), hunk.new.iter().cloned());
+                        offset =
+                            at as isize - start + hunk.new.len() as isize - hunk.old.len() as isize;
+                        HunkStatus::Applied(at + 1)
+                    }
+                    None if find(&lines, &hunk.new, expected).is_some() => {
+                        HunkStatus::AlreadyApplied
+                    }
+                    None => HunkStatus::Conflict,
+                };
+                report.hunks.push((file.path.clone(), i + 1, status));
+            }
+
+            let mut content = String::new();
+            for line in &lines {
+                content.push_str(&line.text);
+                if !line.no_newline {
+                    content.push('\n');
+                }
+            }
+            patched.insert(file.path.clone(), content);
+        }
+
+        if !report.has_conflicts() {
+            contents.extend(patched);
+        }
+        Ok(report)
+    }
+}
+
+/// Parses the hunk whose header is at `lines[start]`, and returns it along with the index of
+/// the line after it.
+fn parse_hunk(lines: &[&str], start: usize) -> Result<(Hunk, usize), String> {
+    let error = || format!("line {}: invalid hunk header {:?}", start + 1, lines[start]);
+    let ranges = lines[start]
+        .strip_prefix("@@ -")
+        .and_then(|header| header.split(" @@").next())
+        .ok_or_else(error)?;
+    let (old_range, new_range) = ranges.split_once(" +").ok_or_else(error)?;
+    let parse_range = |range: &str| -> Result<(usize, usize), String> {
+        let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+        Ok((
+            start.parse().map_err(|_| error())?,
+            count.parse().map_err(|_| error())?,
+        ))
+    };
+    let (old_start, old_count) = parse_range(old_range)?;
+    let (_, new_count) = parse_range(new_range)?;
+
+    let mut hunk = Hunk {
+        // A hunk without old lines, e.g., from `diff -U0`, names the line it adds lines after.
+        old_start: if old_count == 0 {
+            old_start + 1
+        } else {
+            old_start
+        },
+        old: vec![],
+        new: vec![],
+    };
+    let mut i = start + 1;
+    while hunk.old.len() < old_count || hunk.new.len() < new_count {
+        let line = *lines
+            .get(i)
+            .ok_or_else(|| format!("line {}: the hunk ends early", i + 1))?;
+        let text = || Line {
+            text: line[1..].to_owned(),
+            no_newline: false,
+        };
+        match line.chars().next() {
+            Some(' ') | None => {
+                let line = if line.is_empty() {
+                    Line {
+                        text: String::new(),
+                        no_newline: false,
+                    }
+                } else {
+                    text()
+                };
+                hunk.old.push(line.clone());
+                hunk.new.push(line);
+            }
+            Some('-') => hunk.old.push(text()),
+            Some('+') => hunk.new.push(text()),
+            Some('\\') => {}
+            _ => return Err(format!("line {}: unexpected line in a hunk", i + 1)),
+        }
+        i += 1;
+        // `\ No newline at end of file` applies to the line before it.
+        if lines.get(i).is_some_and(|l| l.starts_with('\\')) {
+            let previous = lines[i - 1].chars().next();
+            if previous != Some('+') {
+                if let Some(last) = hunk.old.last_mut() {
+                    last.no_newline = true;
+                }
+            }
+            if previous != Some('-') {
+                if let Some(last) = hunk.new.last_mut() {
+                    last.no_newline = true;
+                }
+            }
+            i += 1;
+        }
+    }
+    Ok((hunk, i))
+}
+
+/// Returns where `needle` matches in `lines`, preferring the match closest to `expected`.
+fn find(lines: &[Line], needle: &[Line], expected: usize) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(expected.min(lines.len()));
+    }
+    if needle.len() > lines.len() {
+        return None;
+    }
+    (0..=lines.len() - needle.len())
+        .filter(|&at| same(&lines[at..at + needle.len()], needle))
+        .min_by_key(|&at| (at as isize - expected as isize).abs())
+}
+
+fn matches_at(lines: &[Line], needle: &[Line], at: usize) -> bool {
+    lines
+        .get(at..at + needle.len())
+        .is_some_and(|lines| same(lines, needle))
+}
+
+fn same(a: &[Line], b: &[Line]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| a.text.trim_end_matches('\r') == b.text.trim_end_matches('\r'))
+}
+
+/// Extracts the path of a `---` or `+++` line, dropping the timestamp some tools append.
+fn diff_path(line: &str) -> &str {
+    line.split('\t').next().unwrap_or(line).trim()
+}
+
+/// Strips the `a/` or `b/` prefix of `git diff`.
+fn strip_prefix(path: &str) -> &str {
+    path.strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(patch: &str, content: &str) -> (String, Vec<HunkStatus>) {
+        let patch = Patch::parse("test.patch", patch).unwrap();
+        let mut contents = BTreeMap::new();
+        contents.insert(PathBuf::from("lib.rs"), content.to_owned());
+        let report = patch.apply(&mut contents).unwrap();
+        let statuses = report
+            .hunks
+            .into_iter()
+            .map(|(_, _, status)| status)
+            .collect();
+        (contents.remove(Path::new("lib.rs")).unwrap(), statuses)
+    }
+
+    #[test]
+    fn parses_format_patch() {
+        let patch = Patch::parse(
+            "0001-fix.patch",
+            "From 1234 Mon Sep 17 00:00:00 2001\nSubject: [PATCH] Fix\n\n---\n lib.rs | 1 +\n\n\
+             diff --git a/lib.rs b/lib.rs\n--- a/lib.rs\n+++ b/lib.rs\n\
+             @@ -1,2 +1,3 @@\n a\n+b\n c\n\
+             @@ -10 +11 @@\n-x\n+y\n-- \n2.20.0\n",
+        )
+        .unwrap();
+        assert_eq!(patch.files.len(), 1);
+        assert_eq!(patch.files[0].path, PathBuf::from("lib.rs"));
+        let hunks = &patch.files[0].hunks;
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(
+            (hunks[0].old_start, hunks[0].old.len(), hunks[0].new.len()),
+            (1, 2, 3)
+        );
+        assert_eq!(
+            (hunks[1].old_start, hunks[1].old.len(), hunks[1].new.len()),
+            (10, 1, 1)
+        );
+    }
+
+    #[test]
+    fn rejects_new_files() {
+        assert!(Patch::parse(
+            "new.patch",
+            "--- /dev/null\n+++ b/new.rs\n@@ -0,0 +1 @@\n+a\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn applies_with_offset() {
+        let patch = "--- a/lib.rs\n+++ b/lib.rs\n@@ -1,2 +1,3 @@\n a\n+b\n c\n";
+        let (content, statuses) = apply(patch, "x\ny\na\nc\n");
+        assert_eq!(content, "x\ny\na\nb\nc\n");
+        assert_eq!(statuses, vec![HunkStatus::Applied(3)]);
+
+        let (content, statuses) = apply(patch, &content);
+        assert_eq!(content, "x\ny\na\nb\nc\n");
+        assert_eq!(statuses, vec![HunkStatus::AlreadyApplied]);
+    }
+
+    #[test]
+    fn applies_deletion_at_end_of_file() {
+        let patch = "--- a/lib.rs\n+++ b/lib.rs\n@@ -1,3 +1,2 @@\n a\n b\n-c\n";
+        let (content, statuses) = apply(patch, "a\nb\nc\n");
+        assert_eq!(content, "a\nb\n");
+        assert_eq!(statuses, vec![HunkStatus::Applied(1)]);
+
+        let (content, statuses) = apply(patch, "a\nb\n");
+        assert_eq!(content, "a\nb\n");
+        assert_eq!(statuses, vec![HunkStatus::AlreadyApplied]);
+    }
+
+    #[test]
+    fn applies_hunk_without_context() {
+        let patch = "--- a/lib.rs\n+++ b/lib.rs\n@@ -2,0 +3 @@\n+}\n";
+        let (content, statuses) = apply(patch, "}\nfn f() {\n");
+        assert_eq!(content, "}\nfn f() {\n}\n");
+        assert_eq!(statuses, vec![HunkStatus::Applied(3)]);
+    }
+
+    #[test]
+    fn reports_conflicts_without_changing_the_file() {
+        let patch = "--- a/lib.rs\n+++ b/lib.rs\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n";
+        let (content, statuses) = apply(patch, "a\nx\n");
+        assert_eq!(content, "a\nx\n");
+        assert_eq!(statuses, vec![HunkStatus::Conflict]);
+    }
+
+    #[test]
+    fn handles_missing_newline_at_end_of_file() {
+        let patch =
+            "--- a/lib.rs\n+++ b/lib.rs\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n\
+                     +c\n";
+        let (content, _) = apply(patch, "a\nb");
+        assert_eq!(content, "a\nc\n");
+
+        let patch = "--- a/lib.rs\n+++ b/lib.rs\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n\
+                     \\ No newline at end of file\n";
+        let (content, _) = apply(patch, "a\nb\n");
+        assert_eq!(content, "a\nc");
+    }
+}
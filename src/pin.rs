@@ -0,0 +1,106 @@
+//! Pinning the dependencies between the copied crates, so that each of them can be published.
+
+use std::collections::BTreeSet;
+
+use cargo_metadata::{DependencyKind, Package};
+use semver::Version;
+
+use crate::{
+    manifest::{dependency_package_name, set_dependency_version, Manifest},
+    version::exact_requirement,
+};
+
+/// Adds an exact requirement on `version`, the version of every copied crate, to every local
+/// dependency of `package`, keeping its `path` so that the generated workspace still builds
+/// locally.
+///
+/// Dev-dependencies are left untouched: `cargo publish` strips dev-dependencies without a
+/// version, which lets crates that depend on each other in tests be published in any order.
+///
+/// Returns a description of every change made.
+pub fn pin_local_dependencies(
+    package: &Package,
+    manifest: &mut Manifest,
+    version: &Version,
+) -> Vec<String> {
+    let mut changes = vec![];
+    let requirement = exact_requirement(version);
+    let local_dependencies: BTreeSet<&str> = package
+        .dependencies
+        .iter()
+        .filter(|dep| dep.source.is_none() && dep.kind != DependencyKind::Development)
+        .map(|dep| dep.name.as_str())
+        .collect();
+
+    for (kind, key, spec) in manifest.dependencies_mut() {
+        let name = dependency_package_name(&key, spec);
+        if kind == DependencyKind::Development || !local_dependencies.contains(name) {
+            continue;
+        }
+        set_dependency_version(spec, &requirement);
+        changes.push(format!("pinned dependency {} to {}", key, requirement));
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::CrateGraph, testing::Workspace};
+
+    #[test]
+    fn pins_local_dependencies() {
+        let workspace = Workspace::new(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"a\", \"b\", \"c\", \"d\"]\n",
+            ),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\n\n\
+                 [dependencies]\nbee = { package = \"b\", path = \"../b\" }\nlog = \"0.4\"\n\n\
+                 [target.'cfg(unix)'.build-dependencies]\nc = { path = \"../c\" }\n\n\
+                 [dev-dependencies]\nd = { path = \"../d\" }\n",
+            ),
+            ("a/src/lib.rs", ""),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.0.0\"\n",
+            ),
+            ("b/src/lib.rs", ""),
+            (
+                "c/Cargo.toml",
+                "[package]\nname = \"c\"\nversion = \"0.0.0\"\n",
+            ),
+            ("c/src/lib.rs", ""),
+            (
+                "d/Cargo.toml",
+                "[package]\nname = \"d\"\nversion = \"0.0.0\"\n",
+            ),
+            ("d/src/lib.rs", ""),
+        ]);
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let krate = graph.find("a").unwrap();
+        let mut manifest = Manifest::open(krate.root_path.join("Cargo.toml")).unwrap();
+        let version = Version::parse("12345.0.0+1.41.0.20191220").unwrap();
+
+        let changes = pin_local_dependencies(graph.package(&krate), &mut manifest, &version);
+        assert_eq!(
+            manifest.render(),
+            "[package]\nname = \"a\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nbee = { package = \"b\", path = \"../b\", version = \"=12345.0.0\" }\n\
+             log = \"0.4\"\n\n\
+             [target.'cfg(unix)'.build-dependencies]\n\
+             c = { path = \"../c\", version = \"=12345.0.0\" }\n\n\
+             [dev-dependencies]\nd = { path = \"../d\" }\n"
+        );
+        assert_eq!(
+            changes,
+            [
+                "pinned dependency bee to =12345.0.0",
+                "pinned dependency c to =12345.0.0",
+            ]
+        );
+    }
+}
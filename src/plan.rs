@@ -0,0 +1,850 @@
+//! Planning what a run does to the output directory, and carrying it out.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::{self, create_dir_all},
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use semver::Version;
+use serde_json::json;
+use toml_edit::{Item, Key, Table};
+use walkdir::WalkDir;
+
+use crate::{
+    cfg_env,
+    config::Config,
+    graph::{CrateGraph, LocalCrate},
+    lockfile::{self, PrunedLock},
+    manifest::{dependency_package_name, Manifest},
+    normalize, package_metadata, patch, pin, prune, rename,
+    transform::{self, Pipeline, SourceEdit},
+    vendor::{self, Vendor},
+    version,
+};
+
+/// Everything a run writes to the output directory.
+#[derive(Debug)]
+pub struct Plan<'a> {
+    pub out: PathBuf,
+    pub version: Version,
+    /// Maps the upstream name of every crate to its new name.
+    pub names: BTreeMap<String, String>,
+    pub crates: Vec<CratePlan<'a>>,
+    /// The content of the generated workspace `Cargo.toml`.
+    pub workspace_manifest: String,
+    /// What was carried over from the upstream workspace `Cargo.toml`.
+    pub workspace_changes: Vec<String>,
+    /// Files generated at the root of the output, e.g., `Cargo.lock`.
+    pub generated_files: Vec<SourceEdit>,
+    /// How the registry dependencies are vendored, if they are.
+    pub vendor: Option<Vendor>,
+}
+
+#[derive(Debug)]
+pub struct CratePlan<'a> {
+    pub krate: LocalCrate<'a>,
+    pub to: PathBuf,
+    pub exclude: Vec<PathBuf>,
+    /// The files to copy, relative to the crate root.
+    pub files: BTreeSet<PathBuf>,
+    /// Total size of the files to copy in bytes.
+    pub size: u64,
+    /// The content of the rewritten `Cargo.toml`.
+    pub manifest: String,
+    pub manifest_changes: Vec<String>,
+    /// Rewritten source files, written over their copies.
+    pub source_edits: Vec<SourceEdit>,
+    /// Files that are not copied from upstream, e.g., a generated `build.rs`.
+    pub generated_files: Vec<SourceEdit>,
+    /// What became of every patch of the crate's series.
+    pub patches: Vec<String>,
+    /// Fields the rewritten manifest lacks to be published, see `package_metadata::missing_fields`.
+    pub missing_fields: Vec<&'static str>,
+}
+
+/// Plans copying `crates` according to `config`, rewriting their sources with `pipeline`, without
+/// touching the filesystem except for reading the upstream checkout.
+pub fn plan<'a>(
+    config: &Config,
+    graph: &CrateGraph<'a>,
+    crates: &BTreeSet<LocalCrate<'a>>,
+    pipeline: &Pipeline,
+) -> io::Result<Plan<'a>> {
+    debug!("Found {} crates", crates.len());
+
+    let names = rename::rename_map(crates, config);
+    let version = version::upstream_version(&config.root, &config.version_template)?;
+    info!("copied crates will have version {}", version);
+
+    let cfg_env_values = cfg_env::values(&config.root, &config.channel)?;
+    let upstream_root = config.root.canonicalize()?;
+    let upstream = Manifest::open(upstream_root.join("Cargo.toml"))?;
+    let mut crate_plans = vec![];
+    for krate in crates {
+        let crate_config = config.krate(krate.name);
+        let exclude = crate_config.map_or(vec![], |c| c.exclude.clone());
+
+        let (files, size) = files(krate, &exclude)?;
+
+        let source_edits = pipeline.run(krate, &files)?;
+        let (mut source_edits, patches) = apply_patches(config, krate, &files, source_edits)?;
+        for edit in &source_edits {
+            // Only overwrite copies, so that a wrong path cannot add a stray file to the output.
+            if !files.contains(&edit.path) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: rewriting {:?} would create a file that is not copied from upstream",
+                        krate.name, edit.path
+                    ),
+                ));
+            }
+        }
+
+        // The manifest is rewritten on top of the patched one, rather than overwritten by it.
+        let manifest_path = krate.root_path.join("Cargo.toml");
+        let (mut manifest, mut manifest_changes) = match source_edits
+            .iter()
+            .position(|edit| edit.path == Path::new("Cargo.toml"))
+        {
+            Some(i) => {
+                let edit = source_edits.remove(i);
+                (
+                    Manifest::parse(&manifest_path, &edit.content)?,
+                    edit.changes,
+                )
+            }
+            None => (Manifest::open(&manifest_path)?, vec![]),
+        };
+        manifest_changes.extend(normalize::normalize(
+            krate,
+            &mut manifest,
+            &upstream,
+            &upstream_root,
+        )?);
+        manifest_changes.extend(prune::prune_dependencies(
+            krate,
+            &mut manifest,
+            graph,
+            crates,
+        ));
+        manifest.set_package_version(&version.to_string());
+        manifest_changes.push(format!("set the version to {}", version));
+        manifest_changes.extend(pin::pin_local_dependencies(
+            graph.package(krate),
+            &mut manifest,
+            &version,
+        ));
+        manifest_changes.extend(rename::rename_crate(krate, &mut manifest, &names));
+        let (metadata_changes, readme) = package_metadata::inject(
+            krate,
+            &mut manifest,
+            &config.package,
+            crate_config.map(|c| &c.package),
+            &files,
+        )?;
+        manifest_changes.extend(metadata_changes);
+
+        let mut generated_files: Vec<_> = readme.into_iter().collect();
+        if config.cfg_env(krate.name) == cfg_env::Mode::BuildScript {
+            let usages = cfg_env::usages(krate, &files)?;
+            if !usages.is_empty() {
+                let edit = generate_build_script(krate, &usages, &cfg_env_values)?;
+                manifest.set_package_build("build.rs");
+                manifest_changes.push("set the build script to build.rs".to_owned());
+                generated_files.push(edit);
+            }
+        }
+
+        crate_plans.push(CratePlan {
+            krate: *krate,
+            to: krate.out_dir(&config.out),
+            exclude,
+            files,
+            size,
+            manifest: manifest.render(),
+            manifest_changes,
+            source_edits,
+            generated_files,
+            patches,
+            missing_fields: package_metadata::missing_fields(&manifest),
+        });
+    }
+
+    let crate_names = crates.iter().map(|krate| krate.name).collect();
+    let lock = lockfile::prune(&upstream_root, &crate_names)?;
+    let (workspace_manifest, workspace_changes) =
+        generate_workspace_manifest(crates, &names, &upstream, lock.as_ref())?;
+    let mut generated_files = vec![];
+    let mut vendor = None;
+    if config.vendor {
+        let lock = lock.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Vendoring needs the upstream lock file, {:?}",
+                    upstream_root.join("Cargo.lock")
+                ),
+            )
+        })?;
+        let (packages, cargo_config) = vendor::plan(&upstream_root, lock)?;
+        vendor = Some(packages);
+        generated_files.push(cargo_config);
+    }
+    if let Some(lock) = lock {
+        generated_files.push(SourceEdit {
+            path: PathBuf::from("Cargo.lock"),
+            changes: vec![format!(
+                "kept {} of the {} external packages of the upstream lock file",
+                lock.packages.len(),
+                lock.total
+            )],
+            content: lock.content,
+        });
+    }
+
+    Ok(Plan {
+        out: config.out.clone(),
+        version,
+        names,
+        crates: crate_plans,
+        workspace_manifest,
+        workspace_changes,
+        generated_files,
+        vendor,
+    })
+}
+
+/// Generates the workspace `Cargo.toml` of the output, carrying over the `[patch]` and
+/// `[profile]` sections of the upstream one, `upstream`, that apply to the copied crates.
+///
+/// Returns its content along with a description of what was carried over.
+fn generate_workspace_manifest(
+    crates: &BTreeSet<LocalCrate<'_>>,
+    names: &BTreeMap<String, String>,
+    upstream: &Manifest,
+    lock: Option<&PrunedLock>,
+) -> io::Result<(String, Vec<String>)> {
+    let mut manifest = Manifest::default();
+    let mut changes = vec![];
+    let members: Vec<_> = crates
+        .iter()
+        .map(|krate| krate.dir_name().to_string_lossy().into_owned())
+        .collect();
+    manifest.set(&["workspace"], Item::Table(Table::new()));
+    manifest.set_workspace_members(&members)?;
+
+    let sources = upstream.get(&["patch"]).and_then(Item::as_table_like);
+    for (source, patches) in sources.iter().flat_map(|sources| sources.iter()) {
+        let patches = match patches.as_table_like() {
+            Some(patches) => patches,
+            None => continue,
+        };
+        for (key, spec) in patches.iter() {
+            let section = format!("[patch.{}] {}", Key::new(source), key);
+            // Paths point into the upstream checkout, which is not copied.
+            if spec.get("path").is_some() {
+                changes.push(format!("left out {}, which is a path", section));
+                continue;
+            }
+            let name = dependency_package_name(key, spec);
+            if lock.is_some_and(|lock| lock.packages.iter().all(|package| package.name != name)) {
+                changes.push(format!(
+                    "left out {}, which no copied crate depends on",
+                    section
+                ));
+                continue;
+            }
+            manifest.set(&["patch", source, key], spec.clone());
+            changes.push(format!("carried over {}", section));
+        }
+    }
+
+    let profiles = upstream.get(&["profile"]).and_then(Item::as_table_like);
+    for (profile, settings) in profiles.iter().flat_map(|profiles| profiles.iter()) {
+        let mut settings = settings.clone();
+        // Overrides for packages refer to the upstream names.
+        if let Some(packages) = settings
+            .get_mut("package")
+            .and_then(Item::as_table_like_mut)
+        {
+            let keys: Vec<String> = packages
+                .iter()
+                .map(|(key, _)| key.to_owned())
+                .filter(|key| key != "*")
+                .collect();
+            for key in keys {
+                let overrides = packages.remove(&key).unwrap();
+                match names.get(&key) {
+                    Some(new_name) => {
+                        packages.insert(new_name, overrides);
+                    }
+                    None => changes.push(format!(
+                        "left out [profile.{}.package.{}], which is not copied",
+                        profile, key
+                    )),
+                }
+            }
+        }
+        manifest.set(&["profile", profile], settings);
+        changes.push(format!("carried over [profile.{}]", profile));
+    }
+
+    Ok((manifest.render(), changes))
+}
+
+/// Applies the patch series of `krate` on top of its transformed sources, `edits`, and returns
+/// the resulting edits along with what became of every patch.
+///
+/// `Cargo.toml` is patched as upstream has it, so the caller rewrites the patched one.
+fn apply_patches(
+    config: &Config,
+    krate: &LocalCrate<'_>,
+    files: &BTreeSet<PathBuf>,
+    edits: Vec<SourceEdit>,
+) -> io::Result<(Vec<SourceEdit>, Vec<String>)> {
+    let series = patch::series(&config.patches, krate.name)?;
+    if series.is_empty() {
+        return Ok((edits, vec![]));
+    }
+
+    let mut changes = BTreeMap::new();
+    let mut contents = BTreeMap::new();
+    for edit in edits {
+        changes.insert(edit.path.clone(), edit.changes);
+        contents.insert(edit.path, edit.content);
+    }
+    let mut reports = vec![];
+    for patch in &series {
+        for file in &patch.files {
+            if files.contains(&file.path) && !contents.contains_key(&file.path) {
+                let content = fs::read_to_string(krate.root_path.join(&file.path))?;
+                contents.insert(file.path.clone(), content);
+            }
+        }
+
+        let report = patch.apply(&mut contents)?;
+        if report.has_conflicts() {
+            if config.on_patch_conflict == patch::OnConflict::Fail {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", krate.name, report),
+                ));
+            }
+            reports.push(format!("skipped {}", report));
+        } else if report.is_empty() {
+            reports.push(format!(
+                "{}: became empty, every hunk is already upstream",
+                patch.name
+            ));
+        } else {
+            for (path, number, status) in &report.hunks {
+                if let patch::HunkStatus::Applied(line) = status {
+                    changes
+                        .entry(path.clone())
+                        .or_insert_with(Vec::new)
+                        .push(format!(
+                            "patch {}: applied hunk #{} at line {}",
+                            patch.name, number, line
+                        ));
+                }
+            }
+            reports.push(report.to_string());
+        }
+    }
+
+    let edits = contents
+        .into_iter()
+        .filter_map(|(path, content)| {
+            let changes = changes.remove(&path)?;
+            Some(SourceEdit {
+                path,
+                content,
+                changes,
+            })
+        })
+        .collect();
+    Ok((edits, reports))
+}
+
+/// Generates a build script for `krate` setting the `CFG_*` variables in `usages`.
+fn generate_build_script(
+    krate: &LocalCrate<'_>,
+    usages: &[cfg_env::Usage],
+    values: &BTreeMap<String, String>,
+) -> io::Result<SourceEdit> {
+    if krate
+        .targets
+        .iter()
+        .any(|target| target.kind.iter().any(|kind| kind == "custom-build"))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} already has a build script, so the `CFG_*` variables it reads cannot be set \
+                 by a generated one: set `cfg-env = \"literal\"` for it instead",
+                krate.name
+            ),
+        ));
+    }
+
+    let names: BTreeSet<_> = usages.iter().map(|usage| usage.name.as_str()).collect();
+    let changes = usages
+        .iter()
+        .map(|usage| {
+            let change = if values.contains_key(&usage.name) {
+                "set"
+            } else {
+                "cannot set, its value is unknown,"
+            };
+            format!(
+                "{} {} read at {}:{}",
+                change,
+                usage.name,
+                usage.path.display(),
+                usage.line
+            )
+        })
+        .collect();
+    Ok(SourceEdit {
+        path: PathBuf::from("build.rs"),
+        content: cfg_env::build_script(&names, values),
+        changes,
+    })
+}
+
+impl Plan<'_> {
+    /// Writes the planned output, removing the output directory first if `force` is set.
+    pub fn execute(&self, force: bool) -> io::Result<()> {
+        if force {
+            fs::remove_dir_all(&self.out)?;
+        }
+
+        for crate_plan in &self.crates {
+            let krate = &crate_plan.krate;
+            info!(
+                "copying {} from {:?} to {:?}",
+                krate.name, krate.root_path, crate_plan.to
+            );
+
+            copy_dir_all(krate.root_path, &crate_plan.to, &crate_plan.exclude)?;
+            fs::write(crate_plan.to.join("Cargo.toml"), &crate_plan.manifest)?;
+            for change in &crate_plan.manifest_changes {
+                info!("{}: {}", krate.name, change);
+            }
+
+            for edit in &crate_plan.source_edits {
+                let to_path = crate_plan.to.join(&edit.path);
+                for change in &edit.changes {
+                    info!("{}: {}: {}", krate.name, edit.path.display(), change);
+                }
+                fs::write(to_path, &edit.content)?;
+            }
+            for patch in &crate_plan.patches {
+                info!("{}: {}", krate.name, patch);
+            }
+            for file in &crate_plan.generated_files {
+                for change in &file.changes {
+                    info!("{}: {}: {}", krate.name, file.path.display(), change);
+                }
+                fs::write(crate_plan.to.join(&file.path), &file.content)?;
+            }
+        }
+
+        fs::write(self.out.join("Cargo.toml"), &self.workspace_manifest)?;
+        for change in &self.workspace_changes {
+            info!("Cargo.toml: {}", change);
+        }
+        for file in &self.generated_files {
+            for change in &file.changes {
+                info!("{}: {}", file.path.display(), change);
+            }
+            let path = self.out.join(&file.path);
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent)?;
+            }
+            fs::write(path, &file.content)?;
+        }
+
+        match &self.vendor {
+            Some(Vendor::Upstream(packages)) => {
+                for package in packages {
+                    info!("vendoring {} {}", package.name, package.version);
+                    copy_dir_all(&package.from, &self.out.join(package.to()), &[])?;
+                }
+            }
+            Some(Vendor::Fetch) => {
+                info!("vendoring with `cargo vendor`");
+                let status = Command::new("cargo")
+                    .args(["vendor", "--versioned-dirs", vendor::DIR])
+                    .current_dir(&self.out)
+                    .stdout(Stdio::null())
+                    .status()?;
+                if !status.success() {
+                    return Err(io::Error::other(format!(
+                        "`cargo vendor` failed in {:?}",
+                        self.out
+                    )));
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Checks that every planned manifest has what crates.io requires.
+    pub fn check_publishable(&self) -> io::Result<()> {
+        let problems: Vec<_> = self
+            .crates
+            .iter()
+            .filter(|crate_plan| !crate_plan.missing_fields.is_empty())
+            .map(|crate_plan| {
+                format!(
+                    "{} lacks {}",
+                    crate_plan.krate.name,
+                    crate_plan.missing_fields.join(", ")
+                )
+            })
+            .collect();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Cannot publish: {}. Set them under `[package]` in the configuration",
+                problems.join("; ")
+            ),
+        ))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let crates: Vec<_> = self
+            .crates
+            .iter()
+            .map(|crate_plan| {
+                json!({
+                    "name": crate_plan.krate.name,
+                    "new_name": self.names[crate_plan.krate.name],
+                    "from": crate_plan.krate.root_path,
+                    "to": crate_plan.to,
+                    "files": crate_plan.files.len(),
+                    "size": crate_plan.size,
+                    "manifest": crate_plan.manifest,
+                    "manifest_changes": crate_plan.manifest_changes,
+                    "transforms": crate_plan.transforms(),
+                    "patches": crate_plan.patches,
+                })
+            })
+            .collect();
+
+        json!({
+            "out": self.out,
+            "version": self.version.to_string(),
+            "crates": crates,
+            "workspace_manifest": self.workspace_manifest,
+            "workspace_changes": self.workspace_changes,
+            "generated_files": self
+                .generated_files
+                .iter()
+                .map(|file| json!({ "path": file.path, "changes": file.changes }))
+                .collect::<Vec<_>>(),
+            "vendor": self.vendor.as_ref().map(|vendor| match vendor {
+                Vendor::Upstream(packages) => packages
+                    .iter()
+                    .map(|package| json!({
+                        "name": package.name,
+                        "version": package.version,
+                        "from": package.from,
+                        "to": package.to(),
+                    }))
+                    .collect(),
+                Vendor::Fetch => json!("cargo vendor"),
+            }),
+        })
+    }
+}
+
+impl CratePlan<'_> {
+    /// Describes the changes planned to the sources.
+    pub fn transforms(&self) -> Vec<String> {
+        self.source_edits
+            .iter()
+            .chain(&self.generated_files)
+            .flat_map(|edit| {
+                edit.changes
+                    .iter()
+                    .map(move |change| format!("{}: {}", edit.path.display(), change))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Plan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Output: {}", self.out.display())?;
+        writeln!(f, "Version: {}", self.version)?;
+
+        for crate_plan in &self.crates {
+            let krate = &crate_plan.krate;
+            writeln!(f)?;
+            writeln!(f, "{} -> {}", krate.name, self.names[krate.name])?;
+            writeln!(f, "  from: {}", krate.root_path.display())?;
+            writeln!(f, "  to: {}", crate_plan.to.display())?;
+            writeln!(
+                f,
+                "  files: {} ({} bytes)",
+                crate_plan.files.len(),
+                crate_plan.size
+            )?;
+            writeln!(f, "  manifest:")?;
+            for change in &crate_plan.manifest_changes {
+                writeln!(f, "    {}", change)?;
+            }
+            let transforms = crate_plan.transforms();
+            if !transforms.is_empty() {
+                writeln!(f, "  transforms:")?;
+                for transform in transforms {
+                    writeln!(f, "    {}", transform)?;
+                }
+            }
+            if !crate_plan.patches.is_empty() {
+                writeln!(f, "  patches:")?;
+                for patch in &crate_plan.patches {
+                    writeln!(f, "    {}", patch)?;
+                }
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{}:", self.out.join("Cargo.toml").display())?;
+        for change in &self.workspace_changes {
+            writeln!(f, "  {}", change)?;
+        }
+        for file in &self.generated_files {
+            for change in &file.changes {
+                writeln!(f, "  {}: {}", file.path.display(), change)?;
+            }
+        }
+        if let Some(vendor) = &self.vendor {
+            for line in vendor.to_string().lines() {
+                writeln!(f, "  {}", line)?;
+            }
+        }
+        write!(f, "{}", self.workspace_manifest)
+    }
+}
+
+/// Returns the files of `krate` to copy, relative to the crate root, and their total size in bytes.
+pub fn files(krate: &LocalCrate<'_>, exclude: &[PathBuf]) -> io::Result<(BTreeSet<PathBuf>, u64)> {
+    let mut files = BTreeSet::new();
+    let mut size = 0;
+    for entry in walk(krate.root_path, exclude) {
+        let entry = entry?;
+        if !entry.file_type().is_dir() {
+            files.insert(transform::relative_path(krate, entry.path())?);
+            size += entry.metadata().map_err(io::Error::from)?.len();
+        }
+    }
+    Ok((files, size))
+}
+
+/// Walks `from`, skipping the files and directories in `exclude`, which are relative to `from`.
+fn walk<'a>(
+    from: &'a Path,
+    exclude: &'a [PathBuf],
+) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> + 'a {
+    WalkDir::new(from).into_iter().filter_entry(move |entry| {
+        let relative_path = entry.path().strip_prefix(from).unwrap();
+        !exclude.iter().any(|path| relative_path.starts_with(path))
+    })
+}
+
+/// Copies `from` to `to`, skipping the files and directories in `exclude`, which are relative to
+/// `from`.
+fn copy_dir_all(from: &Path, to: &Path, exclude: &[PathBuf]) -> io::Result<()> {
+    for entry in walk(from, exclude) {
+        let entry = entry?;
+        let relative_source_path = entry.path().strip_prefix(from).expect("Invalid path");
+        let target_path = to.join(relative_source_path);
+        if entry.file_type().is_dir() {
+            create_dir_all(target_path)?;
+        } else {
+            fs::copy(entry.path(), target_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    #[test]
+    fn carries_over_patches_and_profiles() {
+        let krate = LocalCrate {
+            name: "a",
+            root_path: Path::new("/rust/src/liba"),
+            targets: &[],
+        };
+        let crates = std::iter::once(krate).collect();
+        let names = std::iter::once(("a".to_owned(), "rustfmt-a".to_owned())).collect();
+        let upstream = Manifest::parse(
+            Path::new("Cargo.toml"),
+            "[workspace]\nmembers = [\"src/liba\"]\n\n\
+             [patch.crates-io]\nlog = { git = \"https://github.com/rust-lang/log\" }\n\
+             rand = { path = \"src/tools/rand\" }\n\
+             serde = { git = \"https://github.com/serde-rs/serde\" }\n\n\
+             [profile.release.package.a]\nopt-level = 3\n\n\
+             [profile.release.package.b]\nopt-level = 1\n",
+        )
+        .unwrap();
+        let workspace = Workspace::new(&[(
+            "Cargo.lock",
+            "[[package]]\nname = \"a\"\nversion = \"0.0.0\"\ndependencies = [\n \"log\",\n]\n\n\
+             [[package]]\nname = \"log\"\nversion = \"0.4.8\"\n\
+             source = \"git+https://github.com/rust-lang/log#0123456789\"\n",
+        )]);
+        let lock = lockfile::prune(workspace.root(), &["a"].iter().copied().collect())
+            .unwrap()
+            .unwrap();
+
+        let (manifest, changes) =
+            generate_workspace_manifest(&crates, &names, &upstream, Some(&lock)).unwrap();
+        assert_eq!(
+            manifest,
+            "[workspace]\nmembers = [\n  \"liba\",\n]\n\n\
+             [patch.crates-io]\nlog = { git = \"https://github.com/rust-lang/log\" }\n\n\
+             [profile.release.package.rustfmt-a]\nopt-level = 3\n"
+        );
+        assert_eq!(
+            changes,
+            [
+                "carried over [patch.crates-io] log",
+                "left out [patch.crates-io] rand, which is a path",
+                "left out [patch.crates-io] serde, which no copied crate depends on",
+                "left out [profile.release.package.b], which is not copied",
+                "carried over [profile.release]",
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrites_the_patched_manifest() {
+        let workspace = Workspace::new(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            ("src/version", "1.41.0\n"),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\npublish = false\n\n\
+                 [dependencies]\nb = { path = \"../b\" }\n",
+            ),
+            ("a/src/lib.rs", "pub use b::b;\n"),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.0.0\"\n",
+            ),
+            ("b/src/lib.rs", "pub fn b() {}\n"),
+            (
+                "patches/a/0001-manifest.patch",
+                "--- a/Cargo.toml\n+++ b/Cargo.toml\n@@ -1,3 +1,4 @@\n [package]\n+# Patched.\n \
+                 name = \"a\"\n version = \"0.0.0\"\n",
+            ),
+        ]);
+        workspace.commit();
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let config = Config {
+            root: workspace.root().to_path_buf(),
+            out: workspace.root().join("out"),
+            version_template: "1.0.0".to_owned(),
+            patches: workspace.root().join("patches"),
+            ..Config::default()
+        };
+        let crates = graph.closure(&["a"], &config.dependency_kinds).unwrap();
+        let plan = plan(&config, &graph, &crates, &Pipeline::new()).unwrap();
+
+        let a = plan.crates.iter().find(|c| c.krate.name == "a").unwrap();
+        assert_eq!(
+            a.manifest,
+            "[package]\n# Patched.\nname = \"rustfmt-a\"\nversion = \"1.0.0\"\n\n\
+             [dependencies]\n\
+             b = { path = \"../b\", version = \"=1.0.0\", package = \"rustfmt-b\" }\n\n\
+             [lib]\nname = \"a\"\n"
+        );
+        assert_eq!(
+            a.manifest_changes[0],
+            "patch 0001-manifest.patch: applied hunk #1 at line 1"
+        );
+        assert!(a.source_edits.is_empty());
+    }
+
+    #[test]
+    fn plans_without_writing() {
+        let workspace = Workspace::new(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\"]\n"),
+            ("src/version", "1.41.0\n"),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\n",
+            ),
+            ("a/src/lib.rs", "pub fn a() {}\n"),
+        ]);
+        workspace.commit();
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let out = workspace.root().join("out");
+        let config = Config {
+            root: workspace.root().to_path_buf(),
+            out: out.clone(),
+            version_template: "1.0.0".to_owned(),
+            ..Config::default()
+        };
+        let crates = graph.closure(&["a"], &config.dependency_kinds).unwrap();
+        let plan = plan(&config, &graph, &crates, &Pipeline::new()).unwrap();
+        assert!(!out.exists());
+
+        let a = &plan.crates[0];
+        assert_eq!(
+            plan.to_string(),
+            format!(
+                "Output: {}\nVersion: 1.0.0\n\n\
+                 a -> rustfmt-a\n  from: {}\n  to: {}\n  files: 2 ({} bytes)\n  manifest:\n\
+                 \x20   set the version to 1.0.0\n\
+                 \x20   renamed the package to rustfmt-a\n\n\
+                 {}:\n[workspace]\nmembers = [\n  \"a\",\n]\n",
+                out.display(),
+                a.krate.root_path.display(),
+                out.join("a").display(),
+                a.size,
+                out.join("Cargo.toml").display()
+            )
+        );
+        let json = plan.to_json();
+        assert_eq!(json["version"], "1.0.0");
+        assert_eq!(json["crates"][0]["new_name"], "rustfmt-a");
+        assert_eq!(json["crates"][0]["files"], 2);
+        assert_eq!(json["crates"][0]["manifest"], a.manifest.as_str());
+        assert_eq!(json["vendor"], serde_json::Value::Null);
+
+        plan.execute(false).unwrap();
+        assert_eq!(
+            fs::read_to_string(out.join("a/Cargo.toml")).unwrap(),
+            a.manifest
+        );
+        assert_eq!(
+            fs::read_to_string(out.join("a/src/lib.rs")).unwrap(),
+            "pub fn a() {}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(out.join("Cargo.toml")).unwrap(),
+            plan.workspace_manifest
+        );
+    }
+}
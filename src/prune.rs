@@ -0,0 +1,90 @@
+//! Removing dependencies on local crates that are not copied.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    graph::{CrateGraph, LocalCrate},
+    manifest::{dependency_package_name, Manifest},
+};
+
+/// Removes the dependencies of `krate` on local crates outside of `crates`, e.g., test-only
+/// crates pulled in by dev-dependencies, which would otherwise point to paths that do not exist
+/// in the output.
+///
+/// Returns a description of every change made.
+pub fn prune_dependencies(
+    krate: &LocalCrate<'_>,
+    manifest: &mut Manifest,
+    graph: &CrateGraph<'_>,
+    crates: &BTreeSet<LocalCrate<'_>>,
+) -> Vec<String> {
+    let pruned: Vec<_> = graph
+        .dependencies(krate)
+        .iter()
+        .filter(|edge| !crates.iter().any(|c| c.name == edge.to))
+        .map(|edge| (edge.kind, edge.to))
+        .collect();
+    if pruned.is_empty() {
+        return vec![];
+    }
+
+    manifest
+        .retain_dependencies(|kind, key, spec| {
+            !pruned.contains(&(kind, dependency_package_name(key, spec)))
+        })
+        .into_iter()
+        .map(|key| format!("removed dependency {}, which is not copied", key))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cargo_metadata::DependencyKind;
+
+    use super::*;
+    use crate::testing::Workspace;
+
+    #[test]
+    fn removes_dependencies_on_crates_not_copied() {
+        let workspace = Workspace::new(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n",
+            ),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\n\n\
+                 [dependencies]\nb = { path = \"../b\" }\nlog = \"0.4\"\n\n\
+                 [dev-dependencies]\ntest-helper = { package = \"c\", path = \"../c\" }\n",
+            ),
+            ("a/src/lib.rs", ""),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.0.0\"\n",
+            ),
+            ("b/src/lib.rs", ""),
+            (
+                "c/Cargo.toml",
+                "[package]\nname = \"c\"\nversion = \"0.0.0\"\n",
+            ),
+            ("c/src/lib.rs", ""),
+        ]);
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let crates = graph.closure(&["a"], &[DependencyKind::Normal]).unwrap();
+        let krate = graph.find("a").unwrap();
+        let mut manifest = Manifest::open(krate.root_path.join("Cargo.toml")).unwrap();
+
+        let changes = prune_dependencies(&krate, &mut manifest, &graph, &crates);
+        assert_eq!(
+            changes,
+            ["removed dependency test-helper, which is not copied"]
+        );
+        assert_eq!(
+            manifest.render(),
+            "[package]\nname = \"a\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nb = { path = \"../b\" }\nlog = \"0.4\"\n\n\
+             [dev-dependencies]\n"
+        );
+    }
+}
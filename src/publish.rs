@@ -0,0 +1,176 @@
+//! Publishing the copied crates.
+
+use std::{collections::BTreeMap, io, path::Path, process::Command};
+
+use structopt::StructOpt;
+
+use crate::graph::LocalCrate;
+
+/// Where and how to publish the copied crates.
+#[derive(Debug, StructOpt)]
+pub struct Registry {
+    /// Name of the registry to publish to, as configured in `.cargo/config`.
+    #[structopt(long)]
+    registry: Option<String>,
+    /// URL of the registry index to publish to, e.g., a local stand-in for crates.io.
+    #[structopt(long, conflicts_with = "registry")]
+    index: Option<String>,
+    /// API token to use when publishing. It is passed to cargo through the environment rather
+    /// than its command line, which other users can see.
+    #[structopt(long)]
+    token: Option<String>,
+    /// Do not build the crates before publishing them.
+    #[structopt(long)]
+    no_verify: bool,
+}
+
+/// Publishes the crates copied to `out` in the given order, stopping at the first failure.
+pub fn publish_all(
+    crates: &[LocalCrate<'_>],
+    out: &Path,
+    names: &BTreeMap<String, String>,
+    registry: &Registry,
+) -> io::Result<()> {
+    for (i, krate) in crates.iter().enumerate() {
+        let name = names.get(krate.name).map_or(krate.name, String::as_str);
+        info!("publishing {} from {:?}", name, krate.out_dir(out));
+
+        if let Err(e) = cargo_publish(&krate.out_dir(out).join("Cargo.toml"), registry) {
+            eprintln!("Failed to publish {}: {}", name, e);
+            report_crates("Published", &crates[..i], names);
+            report_crates("Not published", &crates[i..], names);
+            return Err(e);
+        }
+        println!("Published {}", name);
+    }
+
+    Ok(())
+}
+
+fn report_crates(label: &str, crates: &[LocalCrate<'_>], names: &BTreeMap<String, String>) {
+    let crates: Vec<_> = crates
+        .iter()
+        .map(|krate| names.get(krate.name).map_or(krate.name, String::as_str))
+        .collect();
+    if crates.is_empty() {
+        eprintln!("{}: (none)", label);
+    } else {
+        eprintln!("{}: {}", label, crates.join(", "));
+    }
+}
+
+/// Name of the registry given by `--index`.
+const INDEX_REGISTRY: &str = "rustc-publisher";
+
+/// Returns the environment variable setting `key` of the registry named `name`, e.g.,
+/// `CARGO_REGISTRIES_MY_REGISTRY_TOKEN`.
+fn registry_variable(name: &str, key: &str) -> String {
+    format!(
+        "CARGO_REGISTRIES_{}_{}",
+        name.to_uppercase().replace('-', "_"),
+        key
+    )
+}
+
+fn cargo_publish(manifest_path: &Path, registry: &Registry) -> io::Result<()> {
+    let status = publish_command(manifest_path, registry).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "`cargo publish` exited with {}",
+            status
+        )))
+    }
+}
+
+fn publish_command(manifest_path: &Path, registry: &Registry) -> Command {
+    let mut command = Command::new("cargo");
+    command
+        .arg("publish")
+        .arg("--manifest-path")
+        .arg(manifest_path);
+    // The output is generated, so it is never committed to a VCS.
+    command.arg("--allow-dirty");
+    let name = match (&registry.registry, &registry.index) {
+        (Some(name), _) => Some(name.as_str()),
+        // cargo only takes the token of a registry given by its index on the command line, so
+        // name the registry through the environment instead.
+        (None, Some(index)) => {
+            command.env(registry_variable(INDEX_REGISTRY, "INDEX"), index);
+            Some(INDEX_REGISTRY)
+        }
+        (None, None) => None,
+    };
+    if let Some(name) = name {
+        command.arg("--registry").arg(name);
+    }
+    if let Some(token) = &registry.token {
+        let variable = name.map_or("CARGO_REGISTRY_TOKEN".to_owned(), |name| {
+            registry_variable(name, "TOKEN")
+        });
+        command.env(variable, token);
+    }
+    if registry.no_verify {
+        command.arg("--no-verify");
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> (Vec<String>, Vec<(String, String)>) {
+        let registry = Registry::from_iter_safe(["publish"].iter().chain(args)).unwrap();
+        let command = publish_command(Path::new("Cargo.toml"), &registry);
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let envs = command
+            .get_envs()
+            .map(|(key, value)| {
+                let value = value.map_or("", |value| value.to_str().unwrap());
+                (key.to_string_lossy().into_owned(), value.to_owned())
+            })
+            .collect();
+        (args, envs)
+    }
+
+    #[test]
+    fn passes_the_token_through_the_environment() {
+        let publish = ["publish", "--manifest-path", "Cargo.toml", "--allow-dirty"];
+        let pair = |key: &str, value: &str| (key.to_owned(), value.to_owned());
+
+        let (args, envs) = command(&["--token", "secret"]);
+        assert_eq!(args, publish);
+        assert_eq!(envs, [pair("CARGO_REGISTRY_TOKEN", "secret")]);
+
+        let (args, envs) = command(&["--registry", "my-registry", "--token", "secret"]);
+        assert_eq!(
+            args,
+            [&publish[..], &["--registry", "my-registry"]].concat()
+        );
+        assert_eq!(envs, [pair("CARGO_REGISTRIES_MY_REGISTRY_TOKEN", "secret")]);
+
+        let (args, mut envs) = command(&["--index", "file:///index", "--token", "secret"]);
+        envs.sort();
+        assert_eq!(
+            args,
+            [&publish[..], &["--registry", INDEX_REGISTRY]].concat()
+        );
+        assert_eq!(
+            envs,
+            [
+                pair("CARGO_REGISTRIES_RUSTC_PUBLISHER_INDEX", "file:///index"),
+                pair("CARGO_REGISTRIES_RUSTC_PUBLISHER_TOKEN", "secret"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_both_a_registry_and_an_index() {
+        assert!(Registry::from_iter_safe(&["publish", "--registry", "a", "--index", "b"]).is_err());
+    }
+}
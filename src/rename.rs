@@ -0,0 +1,130 @@
+//! Renaming the copied crates so that they can be published without clashing with the
+//! upstream names.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    config::Config,
+    graph::LocalCrate,
+    manifest::{dependency_package_name, set_dependency_package, Manifest},
+};
+
+/// Maps the upstream name of every crate to its new name.
+pub fn rename_map(crates: &BTreeSet<LocalCrate<'_>>, config: &Config) -> BTreeMap<String, String> {
+    crates
+        .iter()
+        .map(|krate| {
+            let new_name = match config.krate(krate.name).and_then(|c| c.name.as_ref()) {
+                Some(name) => name.clone(),
+                None => config.name_template.replace("{name}", krate.name),
+            };
+            (krate.name.to_owned(), new_name)
+        })
+        .collect()
+}
+
+/// Rewrites the manifest of a copied crate according to `names`.
+///
+/// The package itself is renamed, and every dependency on a renamed crate keeps its upstream
+/// name in `Cargo.toml` but points at the renamed package via `package = "..."`, so that
+/// `extern crate` and `use` items in the sources keep compiling.
+///
+/// Returns a description of every change made.
+pub fn rename_crate(
+    krate: &LocalCrate<'_>,
+    manifest: &mut Manifest,
+    names: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut changes = vec![];
+    if let Some(new_name) = names.get(krate.name) {
+        manifest.set_package_name(new_name);
+        manifest.set_lib_name_if_absent(&krate.name.replace('-', "_"));
+        changes.push(format!("renamed the package to {}", new_name));
+    }
+
+    for (_, key, spec) in manifest.dependencies_mut() {
+        let new_name = match names.get(dependency_package_name(&key, spec)) {
+            Some(new_name) => new_name,
+            None => continue,
+        };
+        set_dependency_package(spec, new_name);
+        changes.push(format!("renamed dependency {} to {}", key, new_name));
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::config::CrateConfig;
+
+    fn krate(name: &str) -> LocalCrate<'_> {
+        LocalCrate {
+            name,
+            root_path: Path::new("."),
+            targets: &[],
+        }
+    }
+
+    #[test]
+    fn maps_names_through_the_template() {
+        let mut config = Config::default();
+        let override_name = CrateConfig {
+            name: Some("rustfmt-libsyntax".to_owned()),
+            ..CrateConfig::default()
+        };
+        config.crates.insert("syntax".to_owned(), override_name);
+        let crates = [krate("syntax"), krate("syntax_pos")]
+            .iter()
+            .copied()
+            .collect();
+
+        let names = rename_map(&crates, &config);
+        assert_eq!(names["syntax"], "rustfmt-libsyntax");
+        assert_eq!(names["syntax_pos"], "rustfmt-syntax_pos");
+    }
+
+    #[test]
+    fn renames_the_package_and_its_dependencies() {
+        let mut manifest = Manifest::parse(
+            Path::new("Cargo.toml"),
+            "[package]\nname = \"rustc-ap\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nsyntax_pos = { path = \"../libsyntax_pos\" }\n\
+             pos = { package = \"syntax_pos\", path = \"../libsyntax_pos\" }\nlog = \"0.4\"\n\n\
+             [target.'cfg(unix)'.dependencies]\nsyntax = { path = \"../libsyntax\" }\n",
+        )
+        .unwrap();
+        let names = [
+            ("rustc-ap", "rustfmt-rustc-ap"),
+            ("syntax_pos", "rustfmt-syntax_pos"),
+            ("syntax", "rustfmt-syntax"),
+        ]
+        .iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect();
+
+        let changes = rename_crate(&krate("rustc-ap"), &mut manifest, &names);
+        assert_eq!(
+            manifest.render(),
+            "[package]\nname = \"rustfmt-rustc-ap\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\n\
+             syntax_pos = { path = \"../libsyntax_pos\", package = \"rustfmt-syntax_pos\" }\n\
+             pos = { package = \"rustfmt-syntax_pos\", path = \"../libsyntax_pos\" }\n\
+             log = \"0.4\"\n\n\
+             [target.'cfg(unix)'.dependencies]\n\
+             syntax = { path = \"../libsyntax\", package = \"rustfmt-syntax\" }\n\n\
+             [lib]\nname = \"rustc_ap\"\n"
+        );
+        assert_eq!(
+            changes,
+            [
+                "renamed the package to rustfmt-rustc-ap",
+                "renamed dependency syntax_pos to rustfmt-syntax_pos",
+                "renamed dependency pos to rustfmt-syntax_pos",
+                "renamed dependency syntax to rustfmt-syntax",
+            ]
+        );
+    }
+}
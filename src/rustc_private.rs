@@ -0,0 +1,167 @@
+//! Detecting the crates that need `#![feature(rustc_private)]`.
+//!
+//! A crate needs the feature when it links to a crate of the sysroot that is not part of the
+//! standard library, e.g., `extern crate serialize;`. Such crates are neither local to the
+//! upstream workspace nor declared in the manifest.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt, fs, io,
+    path::PathBuf,
+};
+
+use crate::graph::{CrateGraph, LocalCrate};
+
+/// Crates of the sysroot that can be linked to without `rustc_private`.
+const STANDARD_CRATES: &[&str] = &["std", "core", "alloc", "proc_macro", "test", "self"];
+
+/// An `extern crate` of a crate that only the sysroot provides.
+#[derive(Debug)]
+pub struct Evidence {
+    /// Path of the file relative to the crate root.
+    pub path: PathBuf,
+    pub line: usize,
+    pub name: String,
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`extern crate {}` at {}:{}",
+            self.name,
+            self.path.display(),
+            self.line
+        )
+    }
+}
+
+/// Returns every `extern crate` in the `.rs` files among `files` that needs `rustc_private`.
+pub fn detect(
+    krate: &LocalCrate<'_>,
+    graph: &CrateGraph<'_>,
+    files: &BTreeSet<PathBuf>,
+) -> io::Result<Vec<Evidence>> {
+    let mut known: HashSet<String> = graph
+        .package(krate)
+        .dependencies
+        .iter()
+        .map(|dep| dep.rename.as_ref().unwrap_or(&dep.name).replace('-', "_"))
+        .collect();
+    for local in graph.crates() {
+        known.insert(local.name.replace('-', "_"));
+        if let Some(target) = local.lib_target() {
+            known.insert(target.name.replace('-', "_"));
+        }
+    }
+
+    let mut evidence = vec![];
+    for path in files {
+        if path.extension().is_none_or(|extension| extension != "rs") {
+            continue;
+        }
+        let content = fs::read_to_string(krate.root_path.join(path))?;
+        for (i, line) in content.lines().enumerate() {
+            let name = match extern_crate(line) {
+                Some(name) => name,
+                None => continue,
+            };
+            if !STANDARD_CRATES.contains(&name) && !known.contains(name) {
+                evidence.push(Evidence {
+                    path: path.clone(),
+                    line: i + 1,
+                    name: name.to_owned(),
+                });
+            }
+        }
+    }
+    Ok(evidence)
+}
+
+/// Returns the crate linked to by an `extern crate` item on `line`, if any.
+fn extern_crate(line: &str) -> Option<&str> {
+    let mut line = line.trim_start();
+    // Skip outer attributes on the same line, e.g., `#[macro_use]`.
+    while line.starts_with("#[") {
+        line = line[line.find(']')? + 1..].trim_start();
+    }
+    for visibility in &["pub(crate) ", "pub "] {
+        if let Some(rest) = line.strip_prefix(visibility) {
+            line = rest.trim_start();
+        }
+    }
+    let rest = line.strip_prefix("extern crate ")?.trim_start();
+    let end = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    match &rest[..end] {
+        "" => None,
+        name => Some(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    #[test]
+    fn parses_extern_crates() {
+        assert_eq!(extern_crate("extern crate serialize;"), Some("serialize"));
+        assert_eq!(
+            extern_crate("    #[macro_use] extern crate log;"),
+            Some("log")
+        );
+        assert_eq!(
+            extern_crate("pub(crate) extern crate rustc_data_structures as rds;"),
+            Some("rustc_data_structures")
+        );
+        assert_eq!(extern_crate("pub extern crate self as this;"), Some("self"));
+        assert_eq!(extern_crate("// extern crate serialize;"), None);
+        assert_eq!(extern_crate("use serialize;"), None);
+    }
+
+    #[test]
+    fn detects_crates_only_the_sysroot_provides() {
+        let workspace = Workspace::new(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\n\n\
+                 [dependencies]\nbee = { package = \"b\", path = \"../b\" }\nlog = \"0.4\"\n",
+            ),
+            (
+                "a/src/lib.rs",
+                "extern crate std;\nextern crate bee;\nextern crate b_lib;\n\
+                 #[macro_use]\nextern crate log;\nextern crate serialize;\n",
+            ),
+            ("a/src/parse.rs", "pub extern crate rustc_lexer;\n"),
+            ("a/src/extern.txt", "extern crate syntax;\n"),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.0.0\"\n\n[lib]\nname = \"b_lib\"\n",
+            ),
+            ("b/src/lib.rs", ""),
+        ]);
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let krate = graph.find("a").unwrap();
+        let files = ["src/lib.rs", "src/parse.rs", "src/extern.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+
+        let evidence: Vec<_> = detect(&krate, &graph, &files)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            evidence,
+            [
+                "`extern crate serialize` at src/lib.rs:6",
+                "`extern crate rustc_lexer` at src/parse.rs:1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Temporary upstream workspaces for the tests that need `cargo metadata` or git.
+
+use std::{fs, path::Path, process::Command};
+
+use cargo_metadata::Metadata;
+use tempfile::TempDir;
+
+use crate::workspace;
+
+/// A workspace in a temporary directory, removed when dropped.
+pub struct Workspace {
+    dir: TempDir,
+}
+
+impl Workspace {
+    /// Creates a workspace with the given files, as paths relative to its root and contents.
+    pub fn new(files: &[(&str, &str)]) -> Workspace {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        Workspace { dir }
+    }
+
+    pub fn root(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        workspace::load_metadata(self.root(), &[]).unwrap()
+    }
+
+    /// Commits every file to a new git repository, as upstream is one.
+    pub fn commit(&self) {
+        Workspace::commit_in(self.root());
+    }
+
+    /// Commits every file in `dir` to its git repository, creating it if needed.
+    pub fn commit_in(dir: &Path) {
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(dir)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "`git {}` failed", args.join(" "));
+        };
+        if !dir.join(".git").exists() {
+            git(&["init", "-q"]);
+        }
+        git(&["add", "-A"]);
+        git(&["commit", "-q", "-m", "Upstream"]);
+    }
+}
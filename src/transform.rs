@@ -0,0 +1,577 @@
+//! Rewriting the sources of the copied crates.
+//!
+//! Every `.rs` file copied from upstream goes through a `Pipeline` of `SourceTransform`s, in the
+//! order they were added. The built-in transforms are registered from the configuration, and a
+//! library user can add its own with `Pipeline::add`.
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cfg::ResolveCfg,
+    cfg_env::{self, ReplaceCfgEnv},
+    config::{Config, Replacement},
+    crate_root::Header,
+    graph::{CrateGraph, LocalCrate},
+    plan, rustc_private,
+};
+
+/// The new content of a source file of a copied crate.
+#[derive(Debug)]
+pub struct SourceEdit {
+    /// Path of the file relative to the crate root, which is the same upstream and in the output.
+    pub path: PathBuf,
+    pub content: String,
+    /// What each transform changed, e.g., `inject-features: added #![feature(rustc_private)]`.
+    pub changes: Vec<String>,
+}
+
+/// A source file of a copied crate, as seen by a `SourceTransform`.
+#[derive(Debug)]
+pub struct SourceFile<'a> {
+    pub krate: &'a LocalCrate<'a>,
+    /// Path of the file relative to the crate root.
+    pub path: &'a Path,
+    /// Whether this file is the root of the library target, i.e., `lib.rs`.
+    pub is_crate_root: bool,
+}
+
+/// A rewrite of the source files of the copied crates.
+pub trait SourceTransform {
+    /// A short name identifying the transform in the run log.
+    fn name(&self) -> &str;
+
+    /// Rewrites `content`, the current content of `file`, and returns a description of every
+    /// change made.
+    fn transform(&self, file: &SourceFile<'_>, content: &mut String) -> io::Result<Vec<String>>;
+}
+
+/// The transforms applied to the copied crates, in order.
+#[derive(Default)]
+pub struct Pipeline {
+    /// Transforms along with the name of the only crate they apply to, if any.
+    transforms: Vec<(Option<String>, Box<dyn SourceTransform>)>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Registers the built-in transforms enabled in `config` for `crates`.
+    ///
+    /// `#![feature(rustc_private)]` is injected into the crates that need it according to
+    /// `rustc_private::detect`, unless the configuration says otherwise.
+    pub fn from_config(
+        config: &Config,
+        graph: &CrateGraph<'_>,
+        crates: &BTreeSet<LocalCrate<'_>>,
+    ) -> io::Result<Pipeline> {
+        let mut pipeline = Pipeline::new();
+        // Resolve cfgs first, so that the other transforms only see live code.
+        if let Some(value) = config.bootstrap {
+            pipeline.add(ResolveCfg::new("bootstrap", value));
+        }
+        let cfg_env_values = cfg_env::values(&config.root, &config.channel)?;
+        for krate in crates {
+            if config.cfg_env(krate.name) == cfg_env::Mode::Literal {
+                let transform = ReplaceCfgEnv::new(cfg_env_values.clone());
+                pipeline.add_for_crate(krate.name, transform);
+            }
+
+            let crate_config = config.krate(krate.name);
+            let reason = match crate_config.and_then(|c| c.rustc_private) {
+                Some(true) => Some("`rustc-private = true` in the configuration".to_owned()),
+                Some(false) => None,
+                None => {
+                    let exclude = crate_config.map_or(&[][..], |c| &c.exclude);
+                    let (files, _) = plan::files(krate, exclude)?;
+                    let evidence = rustc_private::detect(krate, graph, &files)?;
+                    for evidence in &evidence {
+                        info!("{} needs rustc_private: {}", krate.name, evidence);
+                    }
+                    evidence.first().map(ToString::to_string)
+                }
+            };
+            if let Some(reason) = reason {
+                let transform = InjectFeatures::new(&["rustc_private"]).with_reason(reason);
+                pipeline.add_for_crate(krate.name, transform);
+            }
+        }
+
+        for (name, crate_config) in &config.crates {
+            if !crate_config.strip_attributes.is_empty() {
+                let transform = StripAttributes::new(&crate_config.strip_attributes);
+                pipeline.add_for_crate(name, transform);
+            }
+            if !crate_config.replace.is_empty() {
+                pipeline.add_for_crate(name, Replace::new(&crate_config.replace));
+            }
+        }
+        Ok(pipeline)
+    }
+
+    /// Adds a transform applied to every crate.
+    pub fn add<T: SourceTransform + 'static>(&mut self, transform: T) -> &mut Pipeline {
+        self.transforms.push((None, Box::new(transform)));
+        self
+    }
+
+    /// Adds a transform applied only to the crate named `krate`.
+    pub fn add_for_crate<T: SourceTransform + 'static>(
+        &mut self,
+        krate: &str,
+        transform: T,
+    ) -> &mut Pipeline {
+        self.transforms
+            .push((Some(krate.to_owned()), Box::new(transform)));
+        self
+    }
+
+    /// Runs the transforms applying to `krate` on its `.rs` files among `files`, which are
+    /// relative to the crate root, and returns the files that changed.
+    pub fn run(
+        &self,
+        krate: &LocalCrate<'_>,
+        files: &BTreeSet<PathBuf>,
+    ) -> io::Result<Vec<SourceEdit>> {
+        let transforms: Vec<_> = self
+            .transforms
+            .iter()
+            .filter(|(only, _)| only.as_ref().is_none_or(|name| name == krate.name))
+            .map(|(_, transform)| transform)
+            .collect();
+        if transforms.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let crate_root = relative_path(krate, krate.lib_path())?;
+        let mut edits = vec![];
+        for path in files {
+            if path.extension().is_none_or(|extension| extension != "rs") {
+                continue;
+            }
+            let file = SourceFile {
+                krate,
+                path,
+                is_crate_root: *path == crate_root,
+            };
+            let original = fs::read_to_string(krate.root_path.join(path))?;
+
+            let mut content = original.clone();
+            let mut changes = vec![];
+            for transform in &transforms {
+                for change in transform.transform(&file, &mut content)? {
+                    changes.push(format!("{}: {}", transform.name(), change));
+                }
+            }
+
+            if content != original {
+                edits.push(SourceEdit {
+                    path: path.clone(),
+                    content,
+                    changes,
+                });
+            }
+        }
+        Ok(edits)
+    }
+}
+
+/// Maps an upstream source file of `krate` to its path relative to the crate root.
+pub fn relative_path(krate: &LocalCrate<'_>, upstream_path: &Path) -> io::Result<PathBuf> {
+    upstream_path
+        .strip_prefix(krate.root_path)
+        .map(Path::to_path_buf)
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{:?} is outside of the crate root of {}, {:?}",
+                    upstream_path, krate.name, krate.root_path
+                ),
+            )
+        })
+}
+
+/// Enables unstable features in the crate root.
+///
+/// A feature that is already enabled is left alone, so running this on its own output changes
+/// nothing. Otherwise the feature is added to the last `#![feature(...)]` attribute, or a new one
+/// is added after the leading inner attributes, below any shebang, license header and doc comments.
+pub struct InjectFeatures {
+    features: Vec<String>,
+    /// Why the features are needed, reported along with every change.
+    reason: Option<String>,
+}
+
+impl InjectFeatures {
+    pub fn new<S: AsRef<str>>(features: &[S]) -> InjectFeatures {
+        InjectFeatures {
+            features: features.iter().map(|f| f.as_ref().to_owned()).collect(),
+            reason: None,
+        }
+    }
+
+    pub fn with_reason(mut self, reason: String) -> InjectFeatures {
+        self.reason = Some(reason);
+        self
+    }
+}
+
+impl SourceTransform for InjectFeatures {
+    fn name(&self) -> &str {
+        "inject-features"
+    }
+
+    fn transform(&self, file: &SourceFile<'_>, content: &mut String) -> io::Result<Vec<String>> {
+        if !file.is_crate_root {
+            return Ok(vec![]);
+        }
+        let mut changes = vec![];
+        for feature in &self.features {
+            let header = Header::parse(content);
+            if header.has_feature(feature) {
+                continue;
+            }
+
+            let (offset, text, change) = match header.features().last() {
+                Some((attribute, _)) => {
+                    // `feature_list` only matches attributes ending with `)`.
+                    let close = content[..attribute.span.end].rfind(')').unwrap();
+                    let before = content[..close].trim_end();
+                    let text = if before.ends_with('(') {
+                        feature.clone()
+                    } else if before.ends_with(',') {
+                        format!(" {},", feature)
+                    } else {
+                        format!(", {}", feature)
+                    };
+                    let change = format!(
+                        "added {} to #![feature(...)] at line {}",
+                        feature,
+                        header.line(attribute.span.start)
+                    );
+                    (before.len(), text, change)
+                }
+                None => {
+                    let attribute = format!("#![feature({})]", feature);
+                    let offset = header.insertion_point;
+                    let text = if offset == 0 || content[..offset].ends_with('\n') {
+                        format!("{}\n", attribute)
+                    } else {
+                        format!("\n{}", attribute)
+                    };
+                    (offset, text, format!("added {}", attribute))
+                }
+            };
+            content.insert_str(offset, &text);
+            match &self.reason {
+                Some(reason) => changes.push(format!("{} because of {}", change, reason)),
+                None => changes.push(change),
+            }
+        }
+        Ok(changes)
+    }
+}
+
+/// Removes single-line attributes with the given paths, e.g., `rustc_diagnostic_item`.
+pub struct StripAttributes {
+    paths: Vec<String>,
+}
+
+impl StripAttributes {
+    pub fn new<S: AsRef<str>>(paths: &[S]) -> StripAttributes {
+        StripAttributes {
+            paths: paths.iter().map(|p| p.as_ref().to_owned()).collect(),
+        }
+    }
+
+    fn matches(&self, line: &str) -> bool {
+        let line = line.trim();
+        let attribute = match line
+            .strip_prefix("#![")
+            .or_else(|| line.strip_prefix("#["))
+            .and_then(|attribute| attribute.strip_suffix(']'))
+        {
+            Some(attribute) => attribute.trim(),
+            None => return false,
+        };
+        let path = attribute
+            .split(|c: char| c == '(' || c == '=' || c.is_whitespace())
+            .next()
+            .unwrap_or("");
+        self.paths.iter().any(|p| p == path)
+    }
+}
+
+impl SourceTransform for StripAttributes {
+    fn name(&self) -> &str {
+        "strip-attributes"
+    }
+
+    fn transform(&self, _file: &SourceFile<'_>, content: &mut String) -> io::Result<Vec<String>> {
+        let mut changes = vec![];
+        let mut result = String::with_capacity(content.len());
+        for (i, line) in content.split_inclusive('\n').enumerate() {
+            if self.matches(line) {
+                changes.push(format!("removed `{}` at line {}", line.trim(), i + 1));
+            } else {
+                result.push_str(line);
+            }
+        }
+        *content = result;
+        Ok(changes)
+    }
+}
+
+/// Replaces text in every source file.
+pub struct Replace {
+    replacements: Vec<Replacement>,
+}
+
+impl Replace {
+    pub fn new(replacements: &[Replacement]) -> Replace {
+        Replace {
+            replacements: replacements.to_vec(),
+        }
+    }
+}
+
+impl SourceTransform for Replace {
+    fn name(&self) -> &str {
+        "replace"
+    }
+
+    fn transform(&self, _file: &SourceFile<'_>, content: &mut String) -> io::Result<Vec<String>> {
+        let mut changes = vec![];
+        for replacement in &self.replacements {
+            let count = content.matches(replacement.from.as_str()).count();
+            if count > 0 {
+                *content = content.replace(&replacement.from, &replacement.to);
+                changes.push(format!(
+                    "replaced {:?} with {:?} {} time(s)",
+                    replacement.from, replacement.to, count
+                ));
+            }
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+    use std::fmt::Write;
+
+    fn inject(features: &[&str], source: &str) -> (String, Vec<String>) {
+        let krate = LocalCrate {
+            name: "test",
+            root_path: Path::new("."),
+            targets: &[],
+        };
+        let file = SourceFile {
+            krate: &krate,
+            path: Path::new("lib.rs"),
+            is_crate_root: true,
+        };
+        let mut content = source.to_owned();
+        let changes = InjectFeatures::new(features)
+            .transform(&file, &mut content)
+            .unwrap();
+        (content, changes)
+    }
+
+    #[test]
+    fn inserts_before_outer_doc_comments() {
+        let (content, _) = inject(&["rustc_private"], "/// Docs of foo.\npub fn foo() {}\n");
+        assert_eq!(
+            content,
+            "#![feature(rustc_private)]\n/// Docs of foo.\npub fn foo() {}\n"
+        );
+    }
+
+    #[test]
+    fn inserts_after_bom_shebang_and_crate_docs() {
+        let (content, _) = inject(
+            &["rustc_private"],
+            "\u{feff}#!/usr/bin/env rust\n//! Crate docs.\n\nfn foo() {}\n",
+        );
+        assert_eq!(
+            content,
+            "\u{feff}#!/usr/bin/env rust\n//! Crate docs.\n#![feature(rustc_private)]\n\
+             \nfn foo() {}\n"
+        );
+    }
+
+    #[test]
+    fn merges_into_multi_line_feature_lists() {
+        let (content, _) = inject(&["c"], "#![feature(\n    a,\n    b,\n)]\n");
+        assert_eq!(content, "#![feature(\n    a,\n    b, c,\n)]\n");
+        let (content, _) = inject(&["c"], "#![feature(a, b)]\n");
+        assert_eq!(content, "#![feature(a, b, c)]\n");
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let source = "//! Docs.\n#![feature(a)]\n/// Docs of foo.\nfn foo() {}\n";
+        let (once, changes) = inject(&["a", "rustc_private"], source);
+        assert_eq!(changes.len(), 1);
+        let (twice, changes) = inject(&["a", "rustc_private"], &once);
+        assert_eq!(twice, once);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn edits_files_at_their_crate_relative_path() {
+        let workspace = Workspace::new(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"compiler/a\"]\n"),
+            (
+                "compiler/a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\n",
+            ),
+            ("compiler/a/src/lib.rs", "mod parse;\n"),
+            ("compiler/a/src/parse/lexer.rs", "// upstream\n"),
+            ("compiler/a/src/parse/mod.rs", "mod lexer;\n"),
+            ("compiler/a/README.md", "upstream\n"),
+        ]);
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let krate = graph.find("a").unwrap();
+        let (files, _) = plan::files(&krate, &[]).unwrap();
+        let mut pipeline = Pipeline::new();
+        pipeline.add(Replace::new(&[Replacement {
+            from: "upstream".to_owned(),
+            to: "downstream".to_owned(),
+        }]));
+
+        let edits = pipeline.run(&krate, &files).unwrap();
+        let paths: Vec<_> = edits.iter().map(|edit| edit.path.as_path()).collect();
+        assert_eq!(paths, [Path::new("src/parse/lexer.rs")]);
+        assert_eq!(edits[0].content, "// downstream\n");
+        assert_eq!(
+            relative_path(&krate, &krate.root_path.join("src/lib.rs")).unwrap(),
+            Path::new("src/lib.rs")
+        );
+        assert!(relative_path(&krate, workspace.root()).is_err());
+    }
+
+    /// Appends a comment naming the file, to check when and in which order transforms run.
+    struct Mark(&'static str);
+
+    impl SourceTransform for Mark {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn transform(
+            &self,
+            file: &SourceFile<'_>,
+            content: &mut String,
+        ) -> io::Result<Vec<String>> {
+            writeln!(content, "// {} {}", self.0, file.is_crate_root).unwrap();
+            Ok(vec![format!("marked {}", file.path.display())])
+        }
+    }
+
+    #[test]
+    fn runs_the_transforms_applying_to_a_crate_in_order() {
+        let workspace = Workspace::new(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\n",
+            ),
+            (
+                "a/src/lib.rs",
+                "#![rustc_diagnostic_item = \"A\"]\nmod ast;\n",
+            ),
+            (
+                "a/src/ast.rs",
+                "#[rustc_diagnostic_item]\npub struct Ast;\n",
+            ),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.0.0\"\n",
+            ),
+            ("b/src/lib.rs", "#[rustc_diagnostic_item]\npub struct B;\n"),
+        ]);
+        let metadata = workspace.metadata();
+        let graph = CrateGraph::new(&metadata);
+        let mut pipeline = Pipeline::new();
+        pipeline
+            .add_for_crate("a", StripAttributes::new(&["rustc_diagnostic_item"]))
+            .add(Mark("first"))
+            .add_for_crate("a", Mark("second"));
+
+        let a = graph.find("a").unwrap();
+        let (files, _) = plan::files(&a, &[]).unwrap();
+        let edits = pipeline.run(&a, &files).unwrap();
+        assert_eq!(edits[0].path, Path::new("src/ast.rs"));
+        assert_eq!(
+            edits[0].content,
+            "pub struct Ast;\n// first false\n// second false\n"
+        );
+        assert_eq!(
+            edits[0].changes,
+            [
+                "strip-attributes: removed `#[rustc_diagnostic_item]` at line 1",
+                "first: marked src/ast.rs",
+                "second: marked src/ast.rs",
+            ]
+        );
+        assert_eq!(edits[1].path, Path::new("src/lib.rs"));
+        assert_eq!(
+            edits[1].content,
+            "mod ast;\n// first true\n// second true\n"
+        );
+
+        let b = graph.find("b").unwrap();
+        let (files, _) = plan::files(&b, &[]).unwrap();
+        let edits = pipeline.run(&b, &files).unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(
+            edits[0].content,
+            "#[rustc_diagnostic_item]\npub struct B;\n// first true\n"
+        );
+    }
+
+    #[test]
+    fn replaces_text() {
+        let krate = LocalCrate {
+            name: "test",
+            root_path: Path::new("."),
+            targets: &[],
+        };
+        let file = SourceFile {
+            krate: &krate,
+            path: Path::new("lib.rs"),
+            is_crate_root: true,
+        };
+        let replace = Replace::new(&[
+            Replacement {
+                from: "rustc_span".to_owned(),
+                to: "syntax_pos".to_owned(),
+            },
+            Replacement {
+                from: "unused".to_owned(),
+                to: "".to_owned(),
+            },
+        ]);
+
+        let mut content = "use rustc_span::Span;\nuse rustc_span::DUMMY_SP;\n".to_owned();
+        let changes = replace.transform(&file, &mut content).unwrap();
+        assert_eq!(
+            content,
+            "use syntax_pos::Span;\nuse syntax_pos::DUMMY_SP;\n"
+        );
+        assert_eq!(
+            changes,
+            ["replaced \"rustc_span\" with \"syntax_pos\" 2 time(s)"]
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Vendoring the registry dependencies of the copied crates, so that the output builds offline.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use toml_edit::Item;
+
+use crate::{
+    lockfile::{LockedPackage, PrunedLock},
+    manifest::Manifest,
+    transform::SourceEdit,
+};
+
+/// Directory of the vendored packages, in both the upstream checkout and the output.
+pub const DIR: &str = "vendor";
+
+/// How the packages are vendored.
+#[derive(Debug)]
+pub enum Vendor {
+    /// Copy them from the upstream `vendor/` directory.
+    Upstream(Vec<VendoredPackage>),
+    /// Upstream has no `vendor/` directory, so run `cargo vendor` in the output.
+    Fetch,
+}
+
+#[derive(Debug)]
+pub struct VendoredPackage {
+    pub name: String,
+    pub version: String,
+    /// The directory of the package in the upstream `vendor/`.
+    pub from: PathBuf,
+}
+
+impl VendoredPackage {
+    /// The directory of the package in the output, relative to the output directory.
+    pub fn to(&self) -> PathBuf {
+        Path::new(DIR).join(format!("{}-{}", self.name, self.version))
+    }
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Vendor::Upstream(packages) => {
+                for package in packages {
+                    writeln!(
+                        f,
+                        "{}: copied from {}",
+                        package.to().display(),
+                        package.from.display()
+                    )?;
+                }
+                Ok(())
+            }
+            Vendor::Fetch => writeln!(f, "{}: fetched with `cargo vendor`", DIR),
+        }
+    }
+}
+
+/// Plans vendoring the registry packages of `lock` from the upstream checkout at `root`.
+///
+/// Returns how they are vendored along with the `.cargo/config` replacing crates.io with them.
+pub fn plan(root: &Path, lock: &PrunedLock) -> io::Result<(Vendor, SourceEdit)> {
+    let mut changes = vec![format!("replaced crates.io with the packages in {}/", DIR)];
+    for package in lock
+        .packages
+        .iter()
+        .filter(|package| !package.is_registry())
+    {
+        changes.push(format!(
+            "left out {} {}, only registry packages are vendored",
+            package.name, package.version
+        ));
+    }
+    let config = SourceEdit {
+        path: Path::new(".cargo").join("config"),
+        content: cargo_config(),
+        changes,
+    };
+
+    let dir = root.join(DIR);
+    if !dir.is_dir() {
+        return Ok((Vendor::Fetch, config));
+    }
+
+    let mut packages = vec![];
+    let mut missing = vec![];
+    for package in lock.packages.iter().filter(|package| package.is_registry()) {
+        match find(&dir, package)? {
+            Some(from) => packages.push(VendoredPackage {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                from,
+            }),
+            None => missing.push(format!("{} {}", package.name, package.version)),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{:?} lacks {}", dir, missing.join(", ")),
+        ));
+    }
+    Ok((Vendor::Upstream(packages), config))
+}
+
+/// Finds `package` in the vendor directory `dir`, where it is either in `<name>-<version>`, or
+/// in `<name>` if it is the only version vendored.
+fn find(dir: &Path, package: &LockedPackage) -> io::Result<Option<PathBuf>> {
+    let candidates = [
+        dir.join(format!("{}-{}", package.name, package.version)),
+        dir.join(&package.name),
+    ];
+    for candidate in &candidates {
+        let manifest_path = candidate.join("Cargo.toml");
+        if !manifest_path.is_file() {
+            continue;
+        }
+        let manifest = Manifest::open(&manifest_path)?;
+        let version = manifest.package_field("version").and_then(Item::as_str);
+        if version == Some(package.version.as_str()) {
+            return Ok(Some(candidate.clone()));
+        }
+    }
+    Ok(None)
+}
+
+fn cargo_config() -> String {
+    format!(
+        "[source.crates-io]\n\
+         replace-with = \"vendored-sources\"\n\
+         \n\
+         [source.vendored-sources]\n\
+         directory = \"{}\"\n",
+        DIR
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lockfile, testing::Workspace};
+
+    const REGISTRY: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+    fn lock_file(rand: &str) -> String {
+        format!(
+            "[[package]]\nname = \"a\"\nversion = \"0.0.0\"\n\
+             dependencies = [\n \"cfg-if\",\n \"log\",\n \"rand\",\n \"tracing\",\n]\n\n\
+             [[package]]\nname = \"cfg-if\"\nversion = \"0.1.10\"\nsource = \"{0}\"\n\n\
+             [[package]]\nname = \"log\"\nversion = \"0.4.8\"\nsource = \"{0}\"\n\n\
+             [[package]]\nname = \"rand\"\nversion = \"{1}\"\nsource = \"{0}\"\n\n\
+             [[package]]\nname = \"tracing\"\nversion = \"0.1.0\"\n\
+             source = \"git+https://github.com/tokio-rs/tracing#abc\"\n",
+            REGISTRY, rand
+        )
+    }
+
+    fn package(name: &str, version: &str) -> String {
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"{}\"\n",
+            name, version
+        )
+    }
+
+    fn vendored(names: &[(&str, &str)]) -> Vec<(String, String)> {
+        names
+            .iter()
+            .map(|(path, name)| (path.to_string(), name.to_string()))
+            .collect()
+    }
+
+    fn upstream(rand: &str, vendored: &[(String, String)]) -> Workspace {
+        let lock_file = lock_file(rand);
+        let mut files = vec![("Cargo.lock", lock_file.as_str())];
+        files.extend(
+            vendored
+                .iter()
+                .map(|(path, content)| (path.as_str(), content.as_str())),
+        );
+        Workspace::new(&files)
+    }
+
+    fn plan_in(workspace: &Workspace) -> io::Result<(Vendor, SourceEdit)> {
+        let crates = ["a"].iter().copied().collect();
+        let lock = lockfile::prune(workspace.root(), &crates)?.unwrap();
+        plan(workspace.root(), &lock)
+    }
+
+    #[test]
+    fn fetches_the_packages_without_an_upstream_vendor_directory() {
+        let workspace = upstream("0.7.0", &[]);
+
+        let (vendor, config) = plan_in(&workspace).unwrap();
+        assert!(matches!(vendor, Vendor::Fetch));
+        assert_eq!(vendor.to_string(), "vendor: fetched with `cargo vendor`\n");
+        assert_eq!(config.path, Path::new(".cargo/config"));
+        assert_eq!(
+            config.content,
+            "[source.crates-io]\nreplace-with = \"vendored-sources\"\n\n\
+             [source.vendored-sources]\ndirectory = \"vendor\"\n"
+        );
+        assert_eq!(
+            config.changes,
+            [
+                "replaced crates.io with the packages in vendor/",
+                "left out tracing 0.1.0, only registry packages are vendored",
+            ]
+        );
+    }
+
+    #[test]
+    fn copies_the_upstream_vendored_packages() {
+        let files = vendored(&[
+            ("vendor/cfg-if/Cargo.toml", &package("cfg-if", "0.1.10")),
+            ("vendor/log/Cargo.toml", &package("log", "0.3.9")),
+            ("vendor/log-0.4.8/Cargo.toml", &package("log", "0.4.8")),
+            ("vendor/rand/Cargo.toml", &package("rand", "0.6.5")),
+        ]);
+
+        let workspace = upstream("0.6.5", &files);
+        let (vendor, _) = plan_in(&workspace).unwrap();
+        let root = workspace.root().join(DIR);
+        assert_eq!(
+            vendor.to_string(),
+            format!(
+                "vendor/cfg-if-0.1.10: copied from {0}/cfg-if\n\
+                 vendor/log-0.4.8: copied from {0}/log-0.4.8\n\
+                 vendor/rand-0.6.5: copied from {0}/rand\n",
+                root.display()
+            )
+        );
+
+        // The only vendored `rand` is another version.
+        let workspace = upstream("0.7.0", &files);
+        let error = plan_in(&workspace).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().ends_with("lacks rand 0.7.0"));
+    }
+}
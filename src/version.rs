@@ -0,0 +1,125 @@
+//! Computing the version of the copied crates from the upstream checkout.
+
+use std::{fs, io, path::Path, process::Command};
+
+use semver::Version;
+
+/// Computes the version of the copied crates by filling `template` with information from the
+/// upstream checkout at `root`.
+///
+/// `{commits}` is the number of commits in the upstream history, which increases monotonically
+/// with every upstream bump, `{release}` is the content of `src/version`, and `{date}` is the
+/// upstream commit date, e.g., `{commits}.0.0+{release}.{date}` gives `12345.0.0+1.41.0.20191220`.
+///
+/// `{commits}` cannot be computed in a shallow clone, which only has part of the history.
+pub fn upstream_version(root: &Path, template: &str) -> io::Result<Version> {
+    let mut version = template.to_owned();
+    if version.contains("{commits}") {
+        // A shallow clone only counts the fetched commits, which would reuse earlier versions.
+        if git(root, &["rev-parse", "--is-shallow-repository"])? == "true" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{:?} is a shallow clone, so `{{commits}}` cannot be counted: run \
+                     `git fetch --unshallow` in it first",
+                    root
+                ),
+            ));
+        }
+        let commits = git(root, &["rev-list", "--count", "HEAD"])?;
+        version = version.replace("{commits}", &commits);
+    }
+    if version.contains("{release}") {
+        let release = fs::read_to_string(root.join("src").join("version"))?;
+        version = version.replace("{release}", release.trim());
+    }
+    if version.contains("{date}") {
+        let date = git(root, &["log", "-1", "--format=%cd", "--date=short"])?;
+        version = version.replace("{date}", &date.replace('-', ""));
+    }
+
+    Version::parse(&version).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid version {:?}: {}", version, e),
+        )
+    })
+}
+
+/// Formats the requirement used by the copied crates to depend on each other.
+pub fn exact_requirement(version: &Version) -> String {
+    // Build metadata is ignored when matching versions, so leave it out of the requirement.
+    let mut version = version.clone();
+    version.build.clear();
+    format!("={}", version)
+}
+
+pub fn git(root: &Path, args: &[&str]) -> io::Result<String> {
+    let output = Command::new("git").args(args).current_dir(root).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "`git {}` failed in {:?}: {}",
+            args.join(" "),
+            root,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    #[test]
+    fn fills_the_template() {
+        let workspace = Workspace::new(&[("src/version", "1.41.0\n")]);
+        workspace.commit();
+        let date = git(
+            workspace.root(),
+            &["log", "-1", "--format=%cd", "--date=short"],
+        )
+        .unwrap();
+
+        let version = upstream_version(workspace.root(), "{commits}.0.0+{release}.{date}").unwrap();
+        assert_eq!(
+            version.to_string(),
+            format!("1.0.0+1.41.0.{}", date.replace('-', ""))
+        );
+        assert_eq!(exact_requirement(&version), "=1.0.0");
+        assert!(upstream_version(workspace.root(), "{release}.0").is_err());
+    }
+
+    #[test]
+    fn refuses_to_count_commits_in_a_shallow_clone() {
+        let workspace = Workspace::new(&[("upstream/src/version", "1.41.0\n")]);
+        let upstream = workspace.root().join("upstream");
+        for i in 0..2 {
+            fs::write(upstream.join("file"), i.to_string()).unwrap();
+            Workspace::commit_in(&upstream);
+        }
+        let url = format!("file://{}", upstream.display());
+        let clone = workspace.root().join("clone");
+        git(
+            workspace.root(),
+            &["clone", "-q", "--depth", "1", &url, clone.to_str().unwrap()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            upstream_version(&upstream, "{commits}.0.0")
+                .unwrap()
+                .to_string(),
+            "2.0.0"
+        );
+        let error = upstream_version(&clone, "{commits}.0.0").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            upstream_version(&clone, "0.0.0+{release}")
+                .unwrap()
+                .to_string(),
+            "0.0.0+1.41.0"
+        );
+    }
+}
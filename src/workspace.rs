@@ -0,0 +1,144 @@
+//! Loading the upstream workspace with `cargo metadata`.
+//!
+//! The upstream workspace has members that cannot be loaded outside of the upstream build
+//! system, e.g., submodules that are not checked out. Instead of editing the upstream manifest,
+//! `cargo metadata` runs on a temporary copy of the workspace root whose manifest only lists the
+//! given members, and whose other entries link to the upstream checkout.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use cargo_metadata::{Metadata, MetadataCommand};
+
+use crate::manifest::Manifest;
+
+/// Runs `cargo metadata` on the upstream workspace at `root`, restricted to `members` if any.
+///
+/// Paths in the returned metadata point to the upstream checkout.
+pub fn load_metadata(root: &Path, members: &[String]) -> io::Result<Metadata> {
+    if members.is_empty() {
+        return exec(root);
+    }
+
+    let root = root.canonicalize()?;
+    let shadow = env::temp_dir().join(format!("rustc-publisher-{}", process::id()));
+    if shadow.exists() {
+        fs::remove_dir_all(&shadow)?;
+    }
+    fs::create_dir_all(&shadow)?;
+    // cargo reports paths under the resolved directory, e.g., `/private/var` on macOS.
+    let shadow = shadow.canonicalize()?;
+    let result = prune(&root, &shadow, members).and_then(|()| exec(&shadow));
+    fs::remove_dir_all(&shadow)?;
+
+    let mut metadata = result?;
+    let unshadow = |path: &mut PathBuf| {
+        if let Ok(relative) = path.strip_prefix(&shadow) {
+            *path = root.join(relative);
+        }
+    };
+    unshadow(&mut metadata.workspace_root);
+    unshadow(&mut metadata.target_directory);
+    for package in &mut metadata.packages {
+        unshadow(&mut package.manifest_path);
+        for target in &mut package.targets {
+            unshadow(&mut target.src_path);
+        }
+    }
+    Ok(metadata)
+}
+
+/// Fills `shadow` with a workspace manifest listing only `members`, and links to every other
+/// entry of `root`.
+fn prune(root: &Path, shadow: &Path, members: &[String]) -> io::Result<()> {
+    let manifest_path = root.join("Cargo.toml");
+    let mut manifest = Manifest::open(&manifest_path)?;
+    debug!(
+        "pruning the members of {:?} to {:?}",
+        manifest_path, members
+    );
+    manifest.set_workspace_members(members)?;
+    fs::write(shadow.join("Cargo.toml"), manifest.render())?;
+
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "Cargo.toml" {
+            continue;
+        }
+        // Copy the lock file so that cargo never writes to the upstream one.
+        if name == "Cargo.lock" {
+            fs::copy(entry.path(), shadow.join(&name))?;
+        } else {
+            symlink(
+                &entry.path(),
+                &shadow.join(&name),
+                entry.file_type()?.is_dir(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn exec(root: &Path) -> io::Result<Metadata> {
+    let mut command = MetadataCommand::new();
+    command.current_dir(root);
+    command.no_deps();
+    command
+        .exec()
+        .map_err(|e| io::Error::other(format!("`cargo metadata` failed in {:?}: {}", root, e)))
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path, _is_dir: bool) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path, is_dir: bool) -> io::Result<()> {
+    if is_dir {
+        std::os::windows::fs::symlink_dir(original, link)
+    } else {
+        std::os::windows::fs::symlink_file(original, link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    #[test]
+    fn loads_the_given_members_from_the_upstream_checkout() {
+        let workspace = Workspace::new(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"a\", \"missing-submodule\"]\n",
+            ),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\n",
+            ),
+            ("a/src/lib.rs", ""),
+        ]);
+        let root = workspace.root().canonicalize().unwrap();
+
+        let metadata = load_metadata(workspace.root(), &["a".to_owned()]).unwrap();
+        assert_eq!(metadata.workspace_root, root);
+        let names: Vec<_> = metadata.packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["a"]);
+        let package = &metadata.packages[0];
+        assert_eq!(package.manifest_path, root.join("a").join("Cargo.toml"));
+        assert_eq!(
+            package.targets[0].src_path,
+            root.join("a").join("src").join("lib.rs")
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("Cargo.toml")).unwrap(),
+            "[workspace]\nmembers = [\"a\", \"missing-submodule\"]\n"
+        );
+    }
+}